@@ -0,0 +1,517 @@
+use dasp::signal::Signal;
+
+use crate::expansion::{self, ExpansionAudio, ExpansionOptions};
+use crate::nsf::NsfHeader;
+use crate::region::Region;
+
+use self::dmc::Dmc;
+use self::frame_counter::{FrameClock, FrameCounter};
+use self::noise::Noise;
+use self::pulse::Pulse;
+use self::triangle::Triangle;
+
+mod dmc;
+mod envelope;
+mod frame_counter;
+mod length_counter;
+mod noise;
+pub(crate) mod pulse;
+mod triangle;
+
+/// A register-driven 2A03/2A07 APU.
+///
+/// Feed it the same writes a CPU would make to $4000-$4017 and pull samples out of it, either
+/// one at a time with `next_sample` or as a dasp `Signal`. Samples are the output of the
+/// nonlinear mixer, from 0.0 (all channels silent) to roughly 1.0.
+///
+/// $4000-$4003    Pulse 1
+/// $4004-$4007    Pulse 2
+/// $4008-$400B    Triangle
+/// $400C-$400F    Noise
+/// $4010-$4013    DMC
+/// $4015          Write: channel enables ---D NT21
+///                Read:  status IF-D NT21
+/// $4017          Frame counter: MI-- ----
+///
+/// Writes anywhere else go to the expansion chips, if any have been added.
+#[derive(Debug)]
+pub struct Apu {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    frame_counter: FrameCounter,
+    even_cycle: bool,
+    cycles_per_sample: f64,
+    cycles_until_sample: f64,
+    /// The mixer output summed over the cycles of the sample in progress
+    sample_sum: f64,
+    sample_cycles: u32,
+    expansions: Vec<Box<dyn ExpansionAudio>>,
+}
+
+impl Apu {
+    pub fn new(region: Region, sample_rate: f64) -> Self {
+        Apu {
+            pulse1: Pulse::pulse1(),
+            pulse2: Pulse::pulse2(),
+            triangle: Triangle::default(),
+            noise: Noise::new(region),
+            dmc: Dmc::new(region),
+            frame_counter: FrameCounter::new(region),
+            even_cycle: true,
+            cycles_per_sample: region.cpu_clock_hz() / sample_rate,
+            cycles_until_sample: region.cpu_clock_hz() / sample_rate,
+            sample_sum: 0.0,
+            sample_cycles: 0,
+            expansions: vec![],
+        }
+    }
+
+    /// An APU in the NSF's region, with the expansion chips it asks for set up with `options`
+    pub fn for_nsf(header: &NsfHeader, sample_rate: f64, options: &ExpansionOptions) -> Self {
+        let region = Region::for_nsf(header);
+        let mut apu = Apu::new(region, sample_rate);
+
+        for chip in expansion::from_expansion_chips(header.expansion_chips(), region, options) {
+            apu.add_expansion(chip);
+        }
+
+        apu
+    }
+
+    pub fn add_expansion(&mut self, chip: Box<dyn ExpansionAudio>) {
+        self.expansions.push(chip);
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write(addr - 0x4000, val),
+            0x4004..=0x4007 => self.pulse2.write(addr - 0x4004, val),
+            0x4008..=0x400B => self.triangle.write(addr - 0x4008, val),
+            0x400C..=0x400F => self.noise.write(addr - 0x400C, val),
+            0x4010..=0x4013 => self.dmc.write(addr - 0x4010, val),
+            0x4015 => {
+                self.pulse1.length_counter.set_enabled(val & 0b1 != 0);
+                self.pulse2.length_counter.set_enabled(val >> 1 & 0b1 != 0);
+                self.triangle
+                    .length_counter
+                    .set_enabled(val >> 2 & 0b1 != 0);
+                self.noise.length_counter.set_enabled(val >> 3 & 0b1 != 0);
+                self.dmc.set_enabled(val >> 4 & 0b1 != 0);
+            }
+            0x4017 => self.frame_counter.write(val, self.even_cycle),
+            0x4020..=0xFFFF => {
+                for chip in &mut self.expansions {
+                    chip.write(addr, val);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// A read of $4015
+    ///
+    /// IF-D NT21    DMC interrupt (I), frame interrupt (F), DMC active (D), length counter > 0 (N/T/2/1)
+    ///
+    /// Reading clears the frame interrupt flag, but not the DMC's.
+    pub fn read_status(&mut self) -> u8 {
+        let status = u8::from(self.dmc.irq_pending) << 7
+            | u8::from(self.frame_counter.irq_pending()) << 6
+            | u8::from(self.dmc.bytes_remaining > 0) << 4
+            | u8::from(self.noise.length_counter.is_active()) << 3
+            | u8::from(self.triangle.length_counter.is_active()) << 2
+            | u8::from(self.pulse2.length_counter.is_active()) << 1
+            | u8::from(self.pulse1.length_counter.is_active());
+
+        self.frame_counter.acknowledge_irq();
+
+        status
+    }
+
+    /// A CPU read of $4015 or one of the expansion chips' registers, or `None` if nothing on the
+    /// APU's side responds to `addr`
+    pub fn read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4015 => Some(self.read_status()),
+            0x4020..=0xFFFF => self.expansions.iter_mut().find_map(|chip| chip.read(addr)),
+            _ => None,
+        }
+    }
+
+    /// Whether the APU is asserting the CPU's IRQ line
+    pub fn irq_pending(&self) -> bool {
+        self.frame_counter.irq_pending() || self.dmc.irq_pending
+    }
+
+    /// Make `data` available to the DMC as if it were mapped at `addr` in CPU memory
+    pub fn load_dmc_memory(&mut self, addr: u16, data: &[u8]) {
+        self.dmc.load_memory(addr, data);
+    }
+
+    /// Run the APU for a single CPU cycle
+    pub fn clock(&mut self) {
+        match self.frame_counter.clock() {
+            FrameClock::Quarter => self.clock_quarter_frame(),
+            FrameClock::Half => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            FrameClock::None => (),
+        }
+
+        if self.even_cycle {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+
+        self.even_cycle = !self.even_cycle;
+
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+
+        for chip in &mut self.expansions {
+            chip.clock();
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.clock_quarter_frame();
+        self.pulse2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_half_frame();
+        self.pulse2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    /// The current output of the nonlinear mixer, plus any expansion chips
+    pub fn output(&self) -> f64 {
+        let pulses = f64::from(self.pulse1.output() + self.pulse2.output());
+        let triangle = f64::from(self.triangle.output());
+        let noise = f64::from(self.noise.output());
+        let dmc = f64::from(self.dmc.output());
+
+        let pulse_out = if pulses == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulses + 100.0)
+        };
+
+        let tnd_sum = triangle / 8227.0 + noise / 12241.0 + dmc / 22638.0;
+
+        let tnd_out = if tnd_sum == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd_sum + 100.0)
+        };
+
+        let expansion_out: f64 = self.expansions.iter().map(|chip| chip.output()).sum();
+
+        pulse_out + tnd_out + expansion_out
+    }
+
+    /// Run the APU for a CPU cycle and add its output to the sample in progress, returning
+    /// true if that sample is complete
+    fn clock_sample(&mut self) -> bool {
+        self.clock();
+        self.sample_sum += self.output();
+        self.sample_cycles += 1;
+        self.cycles_until_sample -= 1.0;
+
+        self.cycles_until_sample <= 0.0
+    }
+
+    /// The average of the mixer output over the sample in progress, which starts a new one
+    fn take_sample(&mut self) -> f64 {
+        let sample = if self.sample_cycles == 0 {
+            self.output()
+        } else {
+            self.sample_sum / f64::from(self.sample_cycles)
+        };
+
+        self.cycles_until_sample += self.cycles_per_sample;
+        self.sample_sum = 0.0;
+        self.sample_cycles = 0;
+
+        sample
+    }
+
+    /// Run the APU for one output sample's worth of CPU cycles, returning the average of the
+    /// mixer output over those cycles
+    pub fn next_sample(&mut self) -> f64 {
+        while !self.clock_sample() {}
+
+        self.take_sample()
+    }
+
+    /// Run the APU for `cycles` CPU cycles (e.g. the length of the instruction a CPU just ran),
+    /// pushing every sample that completes along the way onto `samples`
+    pub fn run(&mut self, cycles: usize, samples: &mut Vec<f64>) {
+        for _ in 0..cycles {
+            if self.clock_sample() {
+                samples.push(self.take_sample());
+            }
+        }
+    }
+}
+
+impl Signal for Apu {
+    type Frame = f64;
+
+    fn next(&mut self) -> f64 {
+        self.next_sample()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How long the NTSC frame counter's 4-step sequence is, in CPU cycles
+    const FOUR_STEP_SEQUENCE: usize = 29830;
+
+    fn apu() -> Apu {
+        Apu::new(Region::Ntsc, 44_100.0)
+    }
+
+    /// The CPU cycles between the first few times `output` rises from `from` to `to`
+    fn cycles_between(apu: &mut Apu, output: impl Fn(&Apu) -> u8, from: u8, to: u8) -> Vec<usize> {
+        let mut edges = vec![];
+        let mut last = output(apu);
+
+        for cycle in 0..100_000 {
+            apu.clock();
+
+            let current = output(apu);
+            if last == from && current == to {
+                edges.push(cycle);
+            }
+            last = current;
+        }
+
+        edges.windows(2).map(|pair| pair[1] - pair[0]).collect()
+    }
+
+    /// How many CPU cycles a channel stays active for, by $4015's `status_bit`
+    fn active_cycles(apu: &mut Apu, status_bit: u8) -> usize {
+        (0..)
+            .find(|_| {
+                apu.clock();
+                apu.read_status() & status_bit == 0
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn pulse_period() {
+        let mut apu = apu();
+        apu.write(0x4015, 0b0000_0001);
+        // 50% duty, length counter halted, constant volume 15
+        apu.write(0x4000, 0b1011_1111);
+        apu.write(0x4002, 0xFD);
+        apu.write(0x4003, 0b0000_1000);
+
+        // 8 steps, each of period + 1 APU cycles, i.e. twice as many CPU cycles
+        let periods = cycles_between(&mut apu, |apu| apu.pulse1.output(), 0, 15);
+        assert!(!periods.is_empty());
+        assert!(periods.iter().all(|&period| period == 16 * (0xFD + 1)));
+    }
+
+    #[test]
+    fn pulse_length_counter() {
+        let mut apu = apu();
+        apu.write(0x4015, 0b0000_0010);
+        apu.write(0x4004, 0b1001_1111);
+        // Length index 0: 10 half frames, i.e. 5 4-step sequences
+        apu.write(0x4007, 0b0000_0000);
+
+        // The tenth half frame lands on the next to last cycle of the fifth sequence
+        assert_eq!(
+            active_cycles(&mut apu, 0b0000_0010),
+            5 * FOUR_STEP_SEQUENCE - 2
+        );
+    }
+
+    #[test]
+    fn length_counter_halt_and_disable() {
+        let mut apu = apu();
+        apu.write(0x4015, 0b0000_0001);
+        // Halted
+        apu.write(0x4000, 0b1011_1111);
+        apu.write(0x4003, 0b0000_0000);
+
+        for _ in 0..10 * FOUR_STEP_SEQUENCE {
+            apu.clock();
+        }
+        assert_eq!(apu.read_status() & 0b0000_0001, 0b0000_0001);
+
+        apu.write(0x4015, 0b0000_0000);
+        assert_eq!(apu.read_status() & 0b0000_0001, 0);
+
+        // Loading a disabled channel does nothing
+        apu.write(0x4003, 0b1111_1000);
+        assert_eq!(apu.read_status() & 0b0000_0001, 0);
+    }
+
+    #[test]
+    fn triangle_period() {
+        let mut apu = apu();
+        apu.write(0x4015, 0b0000_0100);
+        // Linear counter held at 127
+        apu.write(0x4008, 0b1111_1111);
+        apu.write(0x400A, 0x7F);
+        apu.write(0x400B, 0b0000_1000);
+
+        // 32 steps, each of period + 1 CPU cycles
+        let periods = cycles_between(&mut apu, |apu| apu.triangle.output(), 1, 0);
+        assert!(!periods.is_empty());
+        assert!(periods.iter().all(|&period| period == 32 * (0x7F + 1)));
+    }
+
+    #[test]
+    fn triangle_length_counter() {
+        let mut apu = apu();
+        apu.write(0x4015, 0b0000_0100);
+        apu.write(0x4008, 0b0111_1111);
+        // Length index 3: 2 half frames, i.e. 1 4-step sequence
+        apu.write(0x400B, 0b0001_1000);
+
+        assert_eq!(active_cycles(&mut apu, 0b0000_0100), FOUR_STEP_SEQUENCE - 2);
+    }
+
+    #[test]
+    fn noise_period() {
+        let mut apu = apu();
+        apu.write(0x4015, 0b0000_1000);
+        apu.write(0x400C, 0b0011_1111);
+        // Short mode, period index 2: 16 CPU cycles
+        apu.write(0x400E, 0b1000_0010);
+        apu.write(0x400F, 0b0000_1000);
+
+        let outputs: Vec<u8> = (0..10_000)
+            .map(|_| {
+                apu.clock();
+                apu.noise.output()
+            })
+            .collect();
+
+        // The shift register only moves every 16 cycles...
+        let changes: Vec<usize> = (1..outputs.len())
+            .filter(|&i| outputs[i] != outputs[i - 1])
+            .collect();
+        assert!(changes.len() > 1);
+        assert!(changes.iter().all(|&i| (i - changes[0]).is_multiple_of(16)));
+
+        // ...and in short mode it goes round every 93 moves
+        assert!((0..outputs.len() - 93 * 16).all(|i| outputs[i] == outputs[i + 93 * 16]));
+        assert!((0..outputs.len() - 31 * 16).any(|i| outputs[i] != outputs[i + 31 * 16]));
+    }
+
+    #[test]
+    fn noise_length_counter() {
+        let mut apu = apu();
+        apu.write(0x4015, 0b0000_1000);
+        apu.write(0x400C, 0b0001_1111);
+        // Length index 0: 10 half frames
+        apu.write(0x400F, 0b0000_0000);
+
+        assert_eq!(
+            active_cycles(&mut apu, 0b0000_1000),
+            5 * FOUR_STEP_SEQUENCE - 2
+        );
+    }
+
+    fn clock_for(apu: &mut Apu, cycles: usize) {
+        for _ in 0..cycles {
+            apu.clock();
+        }
+    }
+
+    #[test]
+    fn frame_irq_at_end_of_four_step_sequence() {
+        let mut apu = apu();
+
+        clock_for(&mut apu, FOUR_STEP_SEQUENCE - 3);
+        assert!(!apu.irq_pending());
+
+        apu.clock();
+        assert!(apu.irq_pending());
+        assert_eq!(apu.read_status() & 0b0100_0000, 0b0100_0000);
+        assert!(!apu.irq_pending());
+
+        // The flag is set again on each of the sequence's last two cycles...
+        apu.clock();
+        assert!(apu.irq_pending());
+        apu.clock();
+        assert!(apu.irq_pending());
+        apu.read_status();
+
+        // ...and then not until the end of the next one
+        clock_for(&mut apu, FOUR_STEP_SEQUENCE - 3);
+        assert!(!apu.irq_pending());
+        apu.clock();
+        assert!(apu.irq_pending());
+    }
+
+    #[test]
+    fn frame_counter_write_restarts_sequence() {
+        // 3 CPU cycles after a write on an APU cycle, 4 after one between two
+        for (cycles_before, delay) in [(1000, 3), (1001, 4)] {
+            let mut apu = apu();
+            clock_for(&mut apu, cycles_before);
+            apu.write(0x4017, 0b0000_0000);
+
+            clock_for(&mut apu, delay + FOUR_STEP_SEQUENCE - 3);
+            assert!(!apu.irq_pending());
+            apu.clock();
+            assert!(apu.irq_pending());
+        }
+    }
+
+    #[test]
+    fn frame_irq_inhibit() {
+        let mut apu = apu();
+        clock_for(&mut apu, FOUR_STEP_SEQUENCE);
+        assert!(apu.irq_pending());
+
+        // Setting the inhibit flag clears a pending interrupt, too
+        apu.write(0x4017, 0b0100_0000);
+        assert!(!apu.irq_pending());
+
+        clock_for(&mut apu, 3 * FOUR_STEP_SEQUENCE);
+        assert!(!apu.irq_pending());
+        assert_eq!(apu.read_status() & 0b0100_0000, 0);
+    }
+
+    #[test]
+    fn no_frame_irq_in_five_step_mode() {
+        let mut apu = apu();
+        apu.write(0x4017, 0b1000_0000);
+
+        clock_for(&mut apu, 3 * FOUR_STEP_SEQUENCE);
+        assert!(!apu.irq_pending());
+    }
+
+    #[test]
+    fn five_step_write_clocks_length_counters() {
+        let mut apu = apu();
+        apu.write(0x4015, 0b0000_0001);
+        apu.write(0x4000, 0b1001_1111);
+        // Length index 3: 2 half frames
+        apu.write(0x4003, 0b0001_1000);
+
+        // One half frame from the write itself, and one at the second step
+        apu.write(0x4017, 0b1000_0000);
+        clock_for(&mut apu, 3);
+        assert_eq!(apu.read_status() & 0b0000_0001, 0b0000_0001);
+
+        assert_eq!(active_cycles(&mut apu, 0b0000_0001), 14913 - 1);
+    }
+}
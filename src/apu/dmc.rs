@@ -0,0 +1,176 @@
+use crate::region::Region;
+
+/// Timer periods in CPU cycles, indexed by the low four bits of $4010
+const NTSC_RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const PAL_RATES: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+/// Samples are always fetched from $8000-$FFFF
+const SAMPLE_MEMORY_START: u16 = 0x8000;
+const SAMPLE_MEMORY_SIZE: usize = 0x8000;
+
+/// The delta modulation channel, which plays 1-bit delta-encoded samples out of CPU memory
+///
+/// Byte 0: IL-- RRRR    IRQ enable (I), loop (L), rate index (R)
+/// Byte 1: -DDD DDDD    Direct load of the output level (D)
+/// Byte 2: AAAA AAAA    Sample address: $C000 + A * 64
+/// Byte 3: LLLL LLLL    Sample length: L * 16 + 1 bytes
+#[derive(Debug, Clone)]
+pub struct Dmc {
+    rates: &'static [u16; 16],
+    memory: Vec<u8>,
+    irq_enabled: bool,
+    looping: bool,
+    period: u16,
+    timer: u16,
+    output_level: u8,
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    pub(crate) bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+    pub(crate) irq_pending: bool,
+}
+
+impl Dmc {
+    pub fn new(region: Region) -> Self {
+        let rates = match region {
+            Region::Ntsc | Region::Dendy => &NTSC_RATES,
+            Region::Pal => &PAL_RATES,
+        };
+
+        Dmc {
+            rates,
+            memory: vec![0; SAMPLE_MEMORY_SIZE],
+            irq_enabled: false,
+            looping: false,
+            period: rates[0],
+            timer: 0,
+            output_level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq_pending: false,
+        }
+    }
+
+    /// Copy `data` into the sample memory the channel reads from, starting at `addr`.
+    /// Anything outside of $8000-$FFFF is ignored.
+    pub fn load_memory(&mut self, addr: u16, data: &[u8]) {
+        for (offset, byte) in data.iter().enumerate() {
+            let addr = addr as usize + offset;
+
+            if (SAMPLE_MEMORY_START as usize..=0xFFFF).contains(&addr) {
+                self.memory[addr - SAMPLE_MEMORY_START as usize] = *byte;
+            }
+        }
+    }
+
+    /// `reg` is the offset of the register from $4010
+    pub fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                self.irq_enabled = val >> 7 & 0b1 != 0;
+                self.looping = val >> 6 & 0b1 != 0;
+                self.period = self.rates[(val & 0b1111) as usize];
+
+                if !self.irq_enabled {
+                    self.irq_pending = false;
+                }
+            }
+            1 => self.output_level = val & 0b111_1111,
+            2 => self.sample_address = 0xC000 | u16::from(val) << 6,
+            3 => self.sample_length = u16::from(val) << 4 | 1,
+            _ => (),
+        }
+    }
+
+    /// Enabling or disabling the channel through $4015
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq_pending = false;
+
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    fn fetch_sample(&mut self) {
+        if self.sample_buffer.is_some() || self.bytes_remaining == 0 {
+            return;
+        }
+
+        self.sample_buffer =
+            Some(self.memory[(self.current_address - SAMPLE_MEMORY_START) as usize]);
+
+        self.current_address = self.current_address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        self.fetch_sample();
+
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.period - 1;
+
+        if !self.silence {
+            if self.shift_register & 0b1 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    /// 0 to 127
+    pub fn output(&self) -> u8 {
+        self.output_level
+    }
+}
@@ -0,0 +1,53 @@
+/// The volume envelope shared by the pulse and noise channels
+///
+/// Register bits: --LC VVVV    Envelope loop (L), constant volume (C), volume/envelope period (V)
+#[derive(Debug, Clone, Default)]
+pub struct Envelope {
+    start: bool,
+    looping: bool,
+    constant_volume: bool,
+    volume: u8,
+    divider: u8,
+    decay_level: u8,
+}
+
+impl Envelope {
+    pub fn write(&mut self, val: u8) {
+        self.looping = val >> 5 & 0b1 != 0;
+        self.constant_volume = val >> 4 & 0b1 != 0;
+        self.volume = val & 0b1111;
+    }
+
+    /// Restart the envelope on the next quarter frame, as a write to the channel's
+    /// length counter register does
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    /// Clocked by the frame counter every quarter frame
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay_level = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+
+            if self.decay_level > 0 {
+                self.decay_level -= 1;
+            } else if self.looping {
+                self.decay_level = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay_level
+        }
+    }
+}
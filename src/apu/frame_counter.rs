@@ -0,0 +1,128 @@
+use crate::region::Region;
+
+/// CPU cycles into the sequence at which each step lands. The fourth step ends the 4-step
+/// sequence; the 5-step sequence skips it and ends on the fifth.
+const NTSC_STEPS: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+const PAL_STEPS: [u32; 5] = [8313, 16627, 24939, 33253, 41565];
+
+/// What the frame counter clocks on a given CPU cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameClock {
+    None,
+    /// Envelopes and the triangle's linear counter
+    Quarter,
+    /// Everything clocked on a quarter frame, plus length counters and sweep units
+    Half,
+}
+
+/// The frame counter (a.k.a. frame sequencer), which drives the envelopes, sweeps and length
+/// counters at roughly 240 Hz and can raise an IRQ at the end of every 4-step sequence
+///
+/// $4017 write: MI-- ----    Mode (M, 0 = 4-step, 1 = 5-step), IRQ inhibit (I)
+///
+/// Mode 0 (4-step): envelopes on every step, lengths and sweeps on steps 2 and 4, IRQ on step 4
+/// Mode 1 (5-step): envelopes on steps 1, 2, 3 and 5, lengths and sweeps on steps 2 and 5, no IRQ
+#[derive(Debug, Clone)]
+pub struct FrameCounter {
+    steps: &'static [u32; 5],
+    five_step: bool,
+    irq_inhibit: bool,
+    irq_pending: bool,
+    cycle: u32,
+    /// CPU cycles until a $4017 write resets the sequence
+    reset_delay: Option<u8>,
+}
+
+impl FrameCounter {
+    pub fn new(region: Region) -> Self {
+        let steps = match region {
+            Region::Ntsc | Region::Dendy => &NTSC_STEPS,
+            Region::Pal => &PAL_STEPS,
+        };
+
+        FrameCounter {
+            steps,
+            five_step: false,
+            irq_inhibit: false,
+            irq_pending: false,
+            cycle: 0,
+            reset_delay: None,
+        }
+    }
+
+    /// A write to $4017. The sequence restarts 3 CPU cycles later if the write lands on an APU
+    /// cycle (`even_cycle`), 4 if it lands between two.
+    pub fn write(&mut self, val: u8, even_cycle: bool) {
+        self.five_step = val >> 7 & 0b1 != 0;
+        self.irq_inhibit = val >> 6 & 0b1 != 0;
+
+        if self.irq_inhibit {
+            self.irq_pending = false;
+        }
+
+        self.reset_delay = Some(if even_cycle { 3 } else { 4 });
+    }
+
+    /// The frame interrupt flag, bit 6 of $4015
+    pub fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    /// Reading $4015 acknowledges the frame interrupt
+    pub fn acknowledge_irq(&mut self) {
+        self.irq_pending = false;
+    }
+
+    fn raise_irq(&mut self) {
+        if !self.irq_inhibit {
+            self.irq_pending = true;
+        }
+    }
+
+    /// Clocked every CPU cycle
+    pub fn clock(&mut self) -> FrameClock {
+        if let Some(delay) = self.reset_delay {
+            if delay <= 1 {
+                self.reset_delay = None;
+                self.cycle = 0;
+
+                // Switching to the 5-step sequence clocks everything immediately
+                return if self.five_step {
+                    FrameClock::Half
+                } else {
+                    FrameClock::None
+                };
+            }
+
+            self.reset_delay = Some(delay - 1);
+        }
+
+        self.cycle += 1;
+
+        let [quarter1, half1, quarter2, four_step_end, five_step_end] = *self.steps;
+
+        match self.cycle {
+            c if c == quarter1 || c == quarter2 => FrameClock::Quarter,
+            c if c == half1 => FrameClock::Half,
+            c if !self.five_step && c == four_step_end - 1 => {
+                self.raise_irq();
+                FrameClock::None
+            }
+            c if !self.five_step && c == four_step_end => {
+                self.raise_irq();
+                FrameClock::Half
+            }
+            c if !self.five_step && c == four_step_end + 1 => {
+                self.raise_irq();
+                self.cycle = 0;
+                FrameClock::None
+            }
+            c if self.five_step && c == five_step_end => FrameClock::Half,
+            c if self.five_step && c == five_step_end + 1 => {
+                self.cycle = 0;
+                FrameClock::None
+            }
+            _ => FrameClock::None,
+        }
+    }
+}
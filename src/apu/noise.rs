@@ -0,0 +1,96 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+use crate::region::Region;
+
+/// Timer periods in CPU cycles, indexed by the low four bits of $400E
+const NTSC_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PAL_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+/// The noise channel
+///
+/// Byte 0: --LC VVVV    Envelope loop / length counter halt (L), constant volume (C), volume/envelope (V)
+/// Byte 1: ---- ----    Unused
+/// Byte 2: M--- PPPP    Mode (M), period (P)
+/// Byte 3: LLLL L---    Length counter load (L)
+#[derive(Debug, Clone)]
+pub struct Noise {
+    periods: &'static [u16; 16],
+    short_mode: bool,
+    period: u16,
+    timer: u16,
+    shift_register: u16,
+    envelope: Envelope,
+    pub(crate) length_counter: LengthCounter,
+}
+
+impl Noise {
+    pub fn new(region: Region) -> Self {
+        let periods = match region {
+            Region::Ntsc | Region::Dendy => &NTSC_PERIODS,
+            Region::Pal => &PAL_PERIODS,
+        };
+
+        Noise {
+            periods,
+            short_mode: false,
+            period: periods[0],
+            timer: 0,
+            shift_register: 1,
+            envelope: Envelope::default(),
+            length_counter: LengthCounter::default(),
+        }
+    }
+
+    /// `reg` is the offset of the register from $400C
+    pub fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                self.length_counter.set_halted(val >> 5 & 0b1 != 0);
+                self.envelope.write(val);
+            }
+            2 => {
+                self.short_mode = val >> 7 & 0b1 != 0;
+                self.period = self.periods[(val & 0b1111) as usize];
+            }
+            3 => {
+                self.length_counter.load(val);
+                self.envelope.restart();
+            }
+            _ => (),
+        }
+    }
+
+    /// Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift_register ^ self.shift_register >> tap) & 0b1;
+            self.shift_register = self.shift_register >> 1 | feedback << 14;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    /// 0 to 15
+    pub fn output(&self) -> u8 {
+        if !self.length_counter.is_active() || self.shift_register & 0b1 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+
+/// The 8-step waveforms selected by the duty bits
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0], // 12.5%
+    [0, 1, 1, 0, 0, 0, 0, 0], // 25%
+    [0, 1, 1, 1, 1, 0, 0, 0], // 50%
+    [1, 0, 0, 1, 1, 1, 1, 1], // 25% negated
+];
+
+/// The sweep unit, which bends a pulse channel's period up or down every few half frames
+///
+/// Register bits: EPPP NSSS    Enabled (E), period (P), negate (N), shift (S)
+#[derive(Debug, Clone, Default)]
+struct Sweep {
+    enabled: bool,
+    period: u8,
+    negate: bool,
+    shift: u8,
+    divider: u8,
+    reload: bool,
+    /// Pulse 1 negates with ones' complement, so its target period is one lower than pulse 2's
+    ones_complement: bool,
+}
+
+impl Sweep {
+    fn write(&mut self, val: u8) {
+        self.enabled = val >> 7 & 0b1 != 0;
+        self.period = val >> 4 & 0b111;
+        self.negate = val >> 3 & 0b1 != 0;
+        self.shift = val & 0b111;
+        self.reload = true;
+    }
+
+    fn target_period(&self, period: u16) -> u16 {
+        let change = period >> self.shift;
+
+        if self.negate {
+            period
+                .saturating_sub(change)
+                .saturating_sub(self.ones_complement as u16)
+        } else {
+            period + change
+        }
+    }
+
+    /// The channel is silenced whenever its current or target period is out of range, even
+    /// if the sweep unit is disabled
+    fn mutes(&self, period: u16) -> bool {
+        period < 8 || self.target_period(period) > 0x07FF
+    }
+
+    /// Clocked by the frame counter every half frame; returns the channel's new period
+    fn clock(&mut self, period: u16) -> u16 {
+        let mut new_period = period;
+
+        if self.divider == 0 && self.enabled && self.shift > 0 && !self.mutes(period) {
+            new_period = self.target_period(period);
+        }
+
+        if self.divider == 0 || self.reload {
+            self.divider = self.period;
+            self.reload = false;
+        } else {
+            self.divider -= 1;
+        }
+
+        new_period
+    }
+}
+
+/// One of the two pulse (square wave) channels, or one of the MMC5's copies of them
+///
+/// Byte 0: DDLC VVVV    Duty (D), envelope loop / length counter halt (L), constant volume (C), volume/envelope (V)
+/// Byte 1: EPPP NSSS    Sweep unit: enabled (E), period (P), negate (N), shift (S); not on the MMC5
+/// Byte 2: TTTT TTTT    Timer low (T)
+/// Byte 3: LLLL LTTT    Length counter load (L), timer high (T)
+#[derive(Debug, Clone, Default)]
+pub struct Pulse {
+    duty: u8,
+    sequence_step: u8,
+    period: u16,
+    timer: u16,
+    envelope: Envelope,
+    sweep: Option<Sweep>,
+    pub(crate) length_counter: LengthCounter,
+}
+
+impl Pulse {
+    pub fn pulse1() -> Self {
+        Pulse {
+            sweep: Some(Sweep {
+                ones_complement: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    pub fn pulse2() -> Self {
+        Pulse {
+            sweep: Some(Sweep::default()),
+            ..Default::default()
+        }
+    }
+
+    /// The MMC5's pulses have no sweep unit, so nothing mutes them at low periods either
+    pub fn mmc5() -> Self {
+        Pulse::default()
+    }
+
+    /// `reg` is the offset of the register from the channel's base address
+    pub fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                self.duty = val >> 6 & 0b11;
+                self.length_counter.set_halted(val >> 5 & 0b1 != 0);
+                self.envelope.write(val);
+            }
+            1 => {
+                if let Some(sweep) = &mut self.sweep {
+                    sweep.write(val);
+                }
+            }
+            2 => self.period = self.period & 0x0700 | u16::from(val),
+            3 => {
+                self.period = self.period & 0x00FF | u16::from(val & 0b111) << 8;
+                self.length_counter.load(val);
+                self.sequence_step = 0;
+                self.envelope.restart();
+            }
+            _ => (),
+        }
+    }
+
+    /// Clocked every APU cycle, i.e. every other CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.sequence_step = (self.sequence_step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+        if let Some(sweep) = &mut self.sweep {
+            self.period = sweep.clock(self.period);
+        }
+    }
+
+    /// 0 to 15
+    pub fn output(&self) -> u8 {
+        if !self.length_counter.is_active()
+            || self
+                .sweep
+                .as_ref()
+                .is_some_and(|sweep| sweep.mutes(self.period))
+            || DUTY_TABLE[self.duty as usize][self.sequence_step as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
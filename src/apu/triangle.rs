@@ -0,0 +1,79 @@
+use super::length_counter::LengthCounter;
+
+/// The 32-step triangle waveform
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+/// The triangle channel
+///
+/// Byte 0: CRRR RRRR    Length counter halt / linear counter control (C), linear counter load (R)
+/// Byte 1: ---- ----    Unused
+/// Byte 2: TTTT TTTT    Timer low (T)
+/// Byte 3: LLLL LTTT    Length counter load (L), timer high (T)
+#[derive(Debug, Clone, Default)]
+pub struct Triangle {
+    control: bool,
+    linear_counter_load: u8,
+    linear_counter: u8,
+    linear_counter_reload: bool,
+    sequence_step: u8,
+    period: u16,
+    timer: u16,
+    pub(crate) length_counter: LengthCounter,
+}
+
+impl Triangle {
+    /// `reg` is the offset of the register from $4008
+    pub fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                self.control = val >> 7 & 0b1 != 0;
+                self.length_counter.set_halted(self.control);
+                self.linear_counter_load = val & 0b111_1111;
+            }
+            2 => self.period = self.period & 0x0700 | u16::from(val),
+            3 => {
+                self.period = self.period & 0x00FF | u16::from(val & 0b111) << 8;
+                self.length_counter.load(val);
+                self.linear_counter_reload = true;
+            }
+            _ => (),
+        }
+    }
+
+    /// Clocked every CPU cycle; the waveform only advances while both counters are nonzero
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+
+            if self.linear_counter > 0 && self.length_counter.is_active() {
+                self.sequence_step = (self.sequence_step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_counter_reload {
+            self.linear_counter = self.linear_counter_load;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.control {
+            self.linear_counter_reload = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    /// 0 to 15. Silencing the channel freezes the waveform rather than dropping it to 0.
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.sequence_step as usize]
+    }
+}
@@ -0,0 +1,966 @@
+use bitflags::bitflags;
+use std::{error::Error, fmt};
+
+/// Where the stack lives
+const STACK_BASE: u16 = 0x0100;
+
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+/// What the unstable XAA and LXA opcodes OR the accumulator with before ANDing. It varies from
+/// chip to chip; this is the value most 2A03s give.
+const UNSTABLE_MAGIC: u8 = 0xEE;
+
+/// Everything the CPU can read and write: RAM, the APU, the cartridge
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+
+    fn write(&mut self, addr: u16, val: u8);
+}
+
+bitflags! {
+    /// The status register
+    ///
+    /// NV-B DIZC    Negative (N), overflow (V), break (B), decimal (D), IRQ disable (I), zero (Z), carry (C)
+    ///
+    /// B and the unused bit 5 only exist in the copies pushed to the stack; B is set there by
+    /// BRK and PHP, and clear for interrupts. The 2A03 has no decimal mode, so D is just a flag.
+    #[derive(Default)]
+    pub struct Status: u8 {
+        const C = 0b0000_0001;
+        const Z = 0b0000_0010;
+        const I = 0b0000_0100;
+        const D = 0b0000_1000;
+        const B = 0b0001_0000;
+        const U = 0b0010_0000;
+        const V = 0b0100_0000;
+        const N = 0b1000_0000;
+    }
+}
+
+/// The CPU ran into one of the opcodes that lock the 6502 up until it's reset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Jammed {
+    pub pc: u16,
+    pub opcode: u8,
+}
+
+impl fmt::Display for Jammed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the CPU jammed on opcode ${:02X} at ${:04X}",
+            self.opcode, self.pc
+        )
+    }
+}
+
+impl Error for Jammed {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Implied
+    Imp,
+    /// The accumulator
+    Acc,
+    /// #$nn
+    Imm,
+    /// $nn
+    Zp,
+    /// $nn,X
+    Zpx,
+    /// $nn,Y
+    Zpy,
+    /// $nnnn
+    Abs,
+    /// $nnnn,X
+    Abx,
+    /// $nnnn,Y
+    Aby,
+    /// ($nnnn), only used by JMP
+    Ind,
+    /// ($nn,X)
+    Izx,
+    /// ($nn),Y
+    Izy,
+    /// A branch's signed offset
+    Rel,
+}
+
+/// The official instructions, plus the unofficial ones under their most common names
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    ADC,
+    AND,
+    ASL,
+    BCC,
+    BCS,
+    BEQ,
+    BIT,
+    BMI,
+    BNE,
+    BPL,
+    BRK,
+    BVC,
+    BVS,
+    CLC,
+    CLD,
+    CLI,
+    CLV,
+    CMP,
+    CPX,
+    CPY,
+    DEC,
+    DEX,
+    DEY,
+    EOR,
+    INC,
+    INX,
+    INY,
+    JMP,
+    JSR,
+    LDA,
+    LDX,
+    LDY,
+    LSR,
+    NOP,
+    ORA,
+    PHA,
+    PHP,
+    PLA,
+    PLP,
+    ROL,
+    ROR,
+    RTI,
+    RTS,
+    SBC,
+    SEC,
+    SED,
+    SEI,
+    STA,
+    STX,
+    STY,
+    TAX,
+    TAY,
+    TSX,
+    TXA,
+    TXS,
+    TYA,
+    ALR,
+    ANC,
+    ARR,
+    AXS,
+    DCP,
+    ISC,
+    JAM,
+    LAS,
+    LAX,
+    LXA,
+    RLA,
+    RRA,
+    SAX,
+    SHA,
+    SHX,
+    SHY,
+    SLO,
+    SRE,
+    TAS,
+    XAA,
+}
+
+impl Op {
+    /// Whether the instruction takes an extra cycle when indexing crosses a page. Stores and
+    /// read-modify-writes always take it, so it's already in their cycle counts.
+    fn has_page_penalty(self) -> bool {
+        use Op::*;
+
+        matches!(
+            self,
+            ADC | AND | CMP | EOR | LAS | LAX | LDA | LDX | LDY | NOP | ORA | SBC
+        )
+    }
+}
+
+/// Each opcode's instruction, addressing mode and cycle count (before page crossings and taken
+/// branches)
+#[rustfmt::skip]
+const OPCODES: [(Op, Mode, u8); 256] = {
+    use Mode::*;
+    use Op::*;
+
+    [
+        // $00
+        (BRK, Imp, 7), (ORA, Izx, 6), (JAM, Imp, 2), (SLO, Izx, 8), (NOP, Zp, 3), (ORA, Zp, 3), (ASL, Zp, 5), (SLO, Zp, 5),
+        (PHP, Imp, 3), (ORA, Imm, 2), (ASL, Acc, 2), (ANC, Imm, 2), (NOP, Abs, 4), (ORA, Abs, 4), (ASL, Abs, 6), (SLO, Abs, 6),
+        // $10
+        (BPL, Rel, 2), (ORA, Izy, 5), (JAM, Imp, 2), (SLO, Izy, 8), (NOP, Zpx, 4), (ORA, Zpx, 4), (ASL, Zpx, 6), (SLO, Zpx, 6),
+        (CLC, Imp, 2), (ORA, Aby, 4), (NOP, Imp, 2), (SLO, Aby, 7), (NOP, Abx, 4), (ORA, Abx, 4), (ASL, Abx, 7), (SLO, Abx, 7),
+        // $20
+        (JSR, Abs, 6), (AND, Izx, 6), (JAM, Imp, 2), (RLA, Izx, 8), (BIT, Zp, 3), (AND, Zp, 3), (ROL, Zp, 5), (RLA, Zp, 5),
+        (PLP, Imp, 4), (AND, Imm, 2), (ROL, Acc, 2), (ANC, Imm, 2), (BIT, Abs, 4), (AND, Abs, 4), (ROL, Abs, 6), (RLA, Abs, 6),
+        // $30
+        (BMI, Rel, 2), (AND, Izy, 5), (JAM, Imp, 2), (RLA, Izy, 8), (NOP, Zpx, 4), (AND, Zpx, 4), (ROL, Zpx, 6), (RLA, Zpx, 6),
+        (SEC, Imp, 2), (AND, Aby, 4), (NOP, Imp, 2), (RLA, Aby, 7), (NOP, Abx, 4), (AND, Abx, 4), (ROL, Abx, 7), (RLA, Abx, 7),
+        // $40
+        (RTI, Imp, 6), (EOR, Izx, 6), (JAM, Imp, 2), (SRE, Izx, 8), (NOP, Zp, 3), (EOR, Zp, 3), (LSR, Zp, 5), (SRE, Zp, 5),
+        (PHA, Imp, 3), (EOR, Imm, 2), (LSR, Acc, 2), (ALR, Imm, 2), (JMP, Abs, 3), (EOR, Abs, 4), (LSR, Abs, 6), (SRE, Abs, 6),
+        // $50
+        (BVC, Rel, 2), (EOR, Izy, 5), (JAM, Imp, 2), (SRE, Izy, 8), (NOP, Zpx, 4), (EOR, Zpx, 4), (LSR, Zpx, 6), (SRE, Zpx, 6),
+        (CLI, Imp, 2), (EOR, Aby, 4), (NOP, Imp, 2), (SRE, Aby, 7), (NOP, Abx, 4), (EOR, Abx, 4), (LSR, Abx, 7), (SRE, Abx, 7),
+        // $60
+        (RTS, Imp, 6), (ADC, Izx, 6), (JAM, Imp, 2), (RRA, Izx, 8), (NOP, Zp, 3), (ADC, Zp, 3), (ROR, Zp, 5), (RRA, Zp, 5),
+        (PLA, Imp, 4), (ADC, Imm, 2), (ROR, Acc, 2), (ARR, Imm, 2), (JMP, Ind, 5), (ADC, Abs, 4), (ROR, Abs, 6), (RRA, Abs, 6),
+        // $70
+        (BVS, Rel, 2), (ADC, Izy, 5), (JAM, Imp, 2), (RRA, Izy, 8), (NOP, Zpx, 4), (ADC, Zpx, 4), (ROR, Zpx, 6), (RRA, Zpx, 6),
+        (SEI, Imp, 2), (ADC, Aby, 4), (NOP, Imp, 2), (RRA, Aby, 7), (NOP, Abx, 4), (ADC, Abx, 4), (ROR, Abx, 7), (RRA, Abx, 7),
+        // $80
+        (NOP, Imm, 2), (STA, Izx, 6), (NOP, Imm, 2), (SAX, Izx, 6), (STY, Zp, 3), (STA, Zp, 3), (STX, Zp, 3), (SAX, Zp, 3),
+        (DEY, Imp, 2), (NOP, Imm, 2), (TXA, Imp, 2), (XAA, Imm, 2), (STY, Abs, 4), (STA, Abs, 4), (STX, Abs, 4), (SAX, Abs, 4),
+        // $90
+        (BCC, Rel, 2), (STA, Izy, 6), (JAM, Imp, 2), (SHA, Izy, 6), (STY, Zpx, 4), (STA, Zpx, 4), (STX, Zpy, 4), (SAX, Zpy, 4),
+        (TYA, Imp, 2), (STA, Aby, 5), (TXS, Imp, 2), (TAS, Aby, 5), (SHY, Abx, 5), (STA, Abx, 5), (SHX, Aby, 5), (SHA, Aby, 5),
+        // $A0
+        (LDY, Imm, 2), (LDA, Izx, 6), (LDX, Imm, 2), (LAX, Izx, 6), (LDY, Zp, 3), (LDA, Zp, 3), (LDX, Zp, 3), (LAX, Zp, 3),
+        (TAY, Imp, 2), (LDA, Imm, 2), (TAX, Imp, 2), (LXA, Imm, 2), (LDY, Abs, 4), (LDA, Abs, 4), (LDX, Abs, 4), (LAX, Abs, 4),
+        // $B0
+        (BCS, Rel, 2), (LDA, Izy, 5), (JAM, Imp, 2), (LAX, Izy, 5), (LDY, Zpx, 4), (LDA, Zpx, 4), (LDX, Zpy, 4), (LAX, Zpy, 4),
+        (CLV, Imp, 2), (LDA, Aby, 4), (TSX, Imp, 2), (LAS, Aby, 4), (LDY, Abx, 4), (LDA, Abx, 4), (LDX, Aby, 4), (LAX, Aby, 4),
+        // $C0
+        (CPY, Imm, 2), (CMP, Izx, 6), (NOP, Imm, 2), (DCP, Izx, 8), (CPY, Zp, 3), (CMP, Zp, 3), (DEC, Zp, 5), (DCP, Zp, 5),
+        (INY, Imp, 2), (CMP, Imm, 2), (DEX, Imp, 2), (AXS, Imm, 2), (CPY, Abs, 4), (CMP, Abs, 4), (DEC, Abs, 6), (DCP, Abs, 6),
+        // $D0
+        (BNE, Rel, 2), (CMP, Izy, 5), (JAM, Imp, 2), (DCP, Izy, 8), (NOP, Zpx, 4), (CMP, Zpx, 4), (DEC, Zpx, 6), (DCP, Zpx, 6),
+        (CLD, Imp, 2), (CMP, Aby, 4), (NOP, Imp, 2), (DCP, Aby, 7), (NOP, Abx, 4), (CMP, Abx, 4), (DEC, Abx, 7), (DCP, Abx, 7),
+        // $E0
+        (CPX, Imm, 2), (SBC, Izx, 6), (NOP, Imm, 2), (ISC, Izx, 8), (CPX, Zp, 3), (SBC, Zp, 3), (INC, Zp, 5), (ISC, Zp, 5),
+        (INX, Imp, 2), (SBC, Imm, 2), (NOP, Imp, 2), (SBC, Imm, 2), (CPX, Abs, 4), (SBC, Abs, 4), (INC, Abs, 6), (ISC, Abs, 6),
+        // $F0
+        (BEQ, Rel, 2), (SBC, Izy, 5), (JAM, Imp, 2), (ISC, Izy, 8), (NOP, Zpx, 4), (SBC, Zpx, 4), (INC, Zpx, 6), (ISC, Zpx, 6),
+        (SED, Imp, 2), (SBC, Aby, 4), (NOP, Imp, 2), (ISC, Aby, 7), (NOP, Abx, 4), (SBC, Abx, 4), (INC, Abx, 7), (ISC, Abx, 7),
+    ]
+};
+
+/// The 2A03's 6502 core, an instruction at a time.
+///
+/// Every opcode is run, the unofficial ones included:
+/// - read-modify-write instructions write the old value back before the new one, as the 6502
+///   does, so anything watching writes to a register sees both
+/// - the unstable SHA, SHX, SHY and TAS store the register ANDed with the high byte of the base
+///   address plus one, and when indexing crosses a page that value replaces the high byte of
+///   the address written to
+/// - JMP ($xxFF) fetches the high byte of its target from $xx00, not the next page
+/// - the 12 opcodes that jam a real 6502 stop it with a `Jammed` error
+///
+/// There are no interrupt lines: whoever's running it decides when to call `interrupt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cpu {
+    pub pc: u16,
+    pub sp: u8,
+    pub acc: u8,
+    pub x: u8,
+    pub y: u8,
+    pub status: Status,
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Cpu::new()
+    }
+}
+
+impl Cpu {
+    /// A CPU as it is after a reset, before it's fetched the reset vector
+    pub fn new() -> Self {
+        Cpu {
+            pc: 0,
+            sp: 0xFD,
+            acc: 0,
+            x: 0,
+            y: 0,
+            status: Status::I | Status::U,
+        }
+    }
+
+    /// Run the instruction at the program counter, returning how many cycles it took
+    pub fn step(&mut self, bus: &mut impl Bus) -> Result<usize, Jammed> {
+        let opcode_address = self.pc;
+        let opcode = self.fetch(bus);
+        let (op, mode, cycles) = OPCODES[usize::from(opcode)];
+        let mut cycles = usize::from(cycles);
+
+        if op == Op::JAM {
+            self.pc = opcode_address;
+
+            return Err(Jammed {
+                pc: opcode_address,
+                opcode,
+            });
+        }
+
+        let (addr, page_crossed) = self.operand_address(bus, mode);
+
+        if page_crossed && op.has_page_penalty() {
+            cycles += 1;
+        }
+
+        use Op::*;
+
+        match op {
+            ADC => {
+                let val = bus.read(addr);
+                self.adc(val);
+            }
+            AND => {
+                self.acc &= bus.read(addr);
+                self.set_zn(self.acc);
+            }
+            ASL => {
+                self.modify(bus, mode, addr, Cpu::asl);
+            }
+            BCC => cycles += self.branch(bus, addr, !self.status.contains(Status::C)),
+            BCS => cycles += self.branch(bus, addr, self.status.contains(Status::C)),
+            BEQ => cycles += self.branch(bus, addr, self.status.contains(Status::Z)),
+            BMI => cycles += self.branch(bus, addr, self.status.contains(Status::N)),
+            BNE => cycles += self.branch(bus, addr, !self.status.contains(Status::Z)),
+            BPL => cycles += self.branch(bus, addr, !self.status.contains(Status::N)),
+            BVC => cycles += self.branch(bus, addr, !self.status.contains(Status::V)),
+            BVS => cycles += self.branch(bus, addr, self.status.contains(Status::V)),
+            BIT => {
+                let val = bus.read(addr);
+                self.status.set(Status::Z, self.acc & val == 0);
+                self.status.set(Status::V, val & 0x40 != 0);
+                self.status.set(Status::N, val & 0x80 != 0);
+            }
+            BRK => {
+                // BRK skips the byte after it
+                self.pc = self.pc.wrapping_add(1);
+                self.push_pc(bus);
+                self.push(bus, (self.status | Status::B | Status::U).bits());
+                self.status.insert(Status::I);
+                self.pc = read_u16(bus, IRQ_VECTOR);
+            }
+            CLC => self.status.remove(Status::C),
+            CLD => self.status.remove(Status::D),
+            CLI => self.status.remove(Status::I),
+            CLV => self.status.remove(Status::V),
+            SEC => self.status.insert(Status::C),
+            SED => self.status.insert(Status::D),
+            SEI => self.status.insert(Status::I),
+            CMP => {
+                let val = bus.read(addr);
+                self.compare(self.acc, val);
+            }
+            CPX => {
+                let val = bus.read(addr);
+                self.compare(self.x, val);
+            }
+            CPY => {
+                let val = bus.read(addr);
+                self.compare(self.y, val);
+            }
+            DEC => {
+                self.modify(bus, mode, addr, |cpu, val| cpu.set_zn(val.wrapping_sub(1)));
+            }
+            DEX => self.x = self.set_zn(self.x.wrapping_sub(1)),
+            DEY => self.y = self.set_zn(self.y.wrapping_sub(1)),
+            EOR => {
+                self.acc ^= bus.read(addr);
+                self.set_zn(self.acc);
+            }
+            INC => {
+                self.modify(bus, mode, addr, |cpu, val| cpu.set_zn(val.wrapping_add(1)));
+            }
+            INX => self.x = self.set_zn(self.x.wrapping_add(1)),
+            INY => self.y = self.set_zn(self.y.wrapping_add(1)),
+            JMP => self.pc = addr,
+            JSR => {
+                // The address pushed is that of the JSR's last byte
+                self.pc = self.pc.wrapping_sub(1);
+                self.push_pc(bus);
+                self.pc = addr;
+            }
+            LDA => self.acc = self.set_zn(bus.read(addr)),
+            LDX => self.x = self.set_zn(bus.read(addr)),
+            LDY => self.y = self.set_zn(bus.read(addr)),
+            LSR => {
+                self.modify(bus, mode, addr, Cpu::lsr);
+            }
+            NOP => {
+                if mode != Mode::Imp {
+                    bus.read(addr);
+                }
+            }
+            ORA => {
+                self.acc |= bus.read(addr);
+                self.set_zn(self.acc);
+            }
+            PHA => self.push(bus, self.acc),
+            PHP => self.push(bus, (self.status | Status::B | Status::U).bits()),
+            PLA => {
+                let val = self.pop(bus);
+                self.acc = self.set_zn(val);
+            }
+            PLP => {
+                let val = self.pop(bus);
+                self.set_status(val);
+            }
+            ROL => {
+                self.modify(bus, mode, addr, Cpu::rol);
+            }
+            ROR => {
+                self.modify(bus, mode, addr, Cpu::ror);
+            }
+            RTI => {
+                let val = self.pop(bus);
+                self.set_status(val);
+                self.pc = self.pop_u16(bus);
+            }
+            RTS => self.pc = self.pop_u16(bus).wrapping_add(1),
+            SBC => {
+                let val = bus.read(addr);
+                self.adc(!val);
+            }
+            STA => bus.write(addr, self.acc),
+            STX => bus.write(addr, self.x),
+            STY => bus.write(addr, self.y),
+            TAX => self.x = self.set_zn(self.acc),
+            TAY => self.y = self.set_zn(self.acc),
+            TSX => self.x = self.set_zn(self.sp),
+            TXA => self.acc = self.set_zn(self.x),
+            TXS => self.sp = self.x,
+            TYA => self.acc = self.set_zn(self.y),
+
+            ALR => {
+                self.acc &= bus.read(addr);
+                self.acc = self.lsr(self.acc);
+            }
+            ANC => {
+                self.acc &= bus.read(addr);
+                self.set_zn(self.acc);
+                self.status.set(Status::C, self.acc & 0x80 != 0);
+            }
+            ARR => {
+                let val = self.acc & bus.read(addr);
+                self.acc = val >> 1 | u8::from(self.status.contains(Status::C)) << 7;
+                self.set_zn(self.acc);
+                self.status.set(Status::C, self.acc & 0x40 != 0);
+                self.status
+                    .set(Status::V, (self.acc >> 6 ^ self.acc >> 5) & 1 != 0);
+            }
+            AXS => {
+                let val = bus.read(addr);
+                let and = self.acc & self.x;
+                self.status.set(Status::C, and >= val);
+                self.x = self.set_zn(and.wrapping_sub(val));
+            }
+            DCP => {
+                let val = self.modify(bus, mode, addr, |_, val| val.wrapping_sub(1));
+                self.compare(self.acc, val);
+            }
+            ISC => {
+                let val = self.modify(bus, mode, addr, |_, val| val.wrapping_add(1));
+                self.adc(!val);
+            }
+            LAS => {
+                let val = bus.read(addr) & self.sp;
+                self.sp = val;
+                self.x = val;
+                self.acc = self.set_zn(val);
+            }
+            LAX => {
+                let val = bus.read(addr);
+                self.x = val;
+                self.acc = self.set_zn(val);
+            }
+            LXA => {
+                let val = (self.acc | UNSTABLE_MAGIC) & bus.read(addr);
+                self.x = val;
+                self.acc = self.set_zn(val);
+            }
+            RLA => {
+                let val = self.modify(bus, mode, addr, Cpu::rol);
+                self.acc &= val;
+                self.set_zn(self.acc);
+            }
+            RRA => {
+                let val = self.modify(bus, mode, addr, Cpu::ror);
+                self.adc(val);
+            }
+            SAX => bus.write(addr, self.acc & self.x),
+            SHA => self.unstable_store(bus, mode, addr, self.acc & self.x),
+            SHX => self.unstable_store(bus, mode, addr, self.x),
+            SHY => self.unstable_store(bus, mode, addr, self.y),
+            SLO => {
+                let val = self.modify(bus, mode, addr, Cpu::asl);
+                self.acc |= val;
+                self.set_zn(self.acc);
+            }
+            SRE => {
+                let val = self.modify(bus, mode, addr, Cpu::lsr);
+                self.acc ^= val;
+                self.set_zn(self.acc);
+            }
+            TAS => {
+                self.sp = self.acc & self.x;
+                self.unstable_store(bus, mode, addr, self.sp);
+            }
+            XAA => {
+                let val = (self.acc | UNSTABLE_MAGIC) & self.x & bus.read(addr);
+                self.acc = self.set_zn(val);
+            }
+            JAM => unreachable!("jams are handled before the operand is fetched"),
+        }
+
+        Ok(cycles)
+    }
+
+    /// Push the program counter and status and jump to `handler`, the way the CPU takes an
+    /// interrupt. Returns how many cycles that took.
+    pub fn interrupt(&mut self, bus: &mut impl Bus, handler: u16) -> usize {
+        self.push_pc(bus);
+        self.push(bus, ((self.status | Status::U) - Status::B).bits());
+        self.status.insert(Status::I);
+        self.pc = handler;
+
+        7
+    }
+
+    fn fetch(&mut self, bus: &mut impl Bus) -> u8 {
+        let val = bus.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+
+        val
+    }
+
+    fn fetch_u16(&mut self, bus: &mut impl Bus) -> u16 {
+        let lo = self.fetch(bus);
+        let hi = self.fetch(bus);
+
+        u16::from_le_bytes([lo, hi])
+    }
+
+    /// Fetch the operand and work out the address it refers to, and whether indexing crossed a
+    /// page. Immediate operands and branch offsets are at their own address.
+    fn operand_address(&mut self, bus: &mut impl Bus, mode: Mode) -> (u16, bool) {
+        match mode {
+            Mode::Imp | Mode::Acc => (0, false),
+            Mode::Imm | Mode::Rel => {
+                let addr = self.pc;
+                self.pc = self.pc.wrapping_add(1);
+
+                (addr, false)
+            }
+            Mode::Zp => (u16::from(self.fetch(bus)), false),
+            Mode::Zpx => (u16::from(self.fetch(bus).wrapping_add(self.x)), false),
+            Mode::Zpy => (u16::from(self.fetch(bus).wrapping_add(self.y)), false),
+            Mode::Abs => (self.fetch_u16(bus), false),
+            Mode::Abx => {
+                let base = self.fetch_u16(bus);
+                indexed(base, self.x)
+            }
+            Mode::Aby => {
+                let base = self.fetch_u16(bus);
+                indexed(base, self.y)
+            }
+            Mode::Ind => {
+                let pointer = self.fetch_u16(bus);
+                // The high byte comes from the same page as the low byte
+                let hi_pointer = pointer & 0xFF00 | pointer.wrapping_add(1) & 0x00FF;
+
+                (
+                    u16::from_le_bytes([bus.read(pointer), bus.read(hi_pointer)]),
+                    false,
+                )
+            }
+            Mode::Izx => {
+                let pointer = self.fetch(bus).wrapping_add(self.x);
+                (read_zero_page_u16(bus, pointer), false)
+            }
+            Mode::Izy => {
+                let pointer = self.fetch(bus);
+                let base = read_zero_page_u16(bus, pointer);
+                indexed(base, self.y)
+            }
+        }
+    }
+
+    fn set_zn(&mut self, val: u8) -> u8 {
+        self.status.set(Status::Z, val == 0);
+        self.status.set(Status::N, val & 0x80 != 0);
+
+        val
+    }
+
+    /// Set the status register from a copy pulled off the stack
+    fn set_status(&mut self, val: u8) {
+        self.status = (Status::from_bits_truncate(val) | Status::U) - Status::B;
+    }
+
+    fn adc(&mut self, val: u8) {
+        let sum = u16::from(self.acc) + u16::from(val) + u16::from(self.status.contains(Status::C));
+        let result = sum as u8;
+
+        self.status.set(Status::C, sum > 0xFF);
+        self.status
+            .set(Status::V, (self.acc ^ result) & (val ^ result) & 0x80 != 0);
+        self.acc = self.set_zn(result);
+    }
+
+    fn compare(&mut self, reg: u8, val: u8) {
+        self.status.set(Status::C, reg >= val);
+        self.set_zn(reg.wrapping_sub(val));
+    }
+
+    fn asl(&mut self, val: u8) -> u8 {
+        self.status.set(Status::C, val & 0x80 != 0);
+        self.set_zn(val << 1)
+    }
+
+    fn lsr(&mut self, val: u8) -> u8 {
+        self.status.set(Status::C, val & 0x01 != 0);
+        self.set_zn(val >> 1)
+    }
+
+    fn rol(&mut self, val: u8) -> u8 {
+        let carry = u8::from(self.status.contains(Status::C));
+        self.status.set(Status::C, val & 0x80 != 0);
+        self.set_zn(val << 1 | carry)
+    }
+
+    fn ror(&mut self, val: u8) -> u8 {
+        let carry = u8::from(self.status.contains(Status::C));
+        self.status.set(Status::C, val & 0x01 != 0);
+        self.set_zn(val >> 1 | carry << 7)
+    }
+
+    /// Apply `f` to the accumulator or the byte at `addr`, writing the old byte back first as
+    /// the 6502 does. Returns the new value.
+    fn modify(
+        &mut self,
+        bus: &mut impl Bus,
+        mode: Mode,
+        addr: u16,
+        f: impl FnOnce(&mut Self, u8) -> u8,
+    ) -> u8 {
+        if mode == Mode::Acc {
+            self.acc = f(self, self.acc);
+
+            return self.acc;
+        }
+
+        let old = bus.read(addr);
+        bus.write(addr, old);
+
+        let new = f(self, old);
+        bus.write(addr, new);
+
+        new
+    }
+
+    /// SHA, SHX, SHY and TAS's store of `reg`, ANDed with the high byte of the base address plus
+    /// one
+    fn unstable_store(&mut self, bus: &mut impl Bus, mode: Mode, addr: u16, reg: u8) {
+        let index = if mode == Mode::Abx { self.x } else { self.y };
+        let base = addr.wrapping_sub(u16::from(index));
+        let val = reg & ((base >> 8) as u8).wrapping_add(1);
+
+        let addr = if base & 0xFF00 != addr & 0xFF00 {
+            u16::from(val) << 8 | addr & 0x00FF
+        } else {
+            addr
+        };
+
+        bus.write(addr, val);
+    }
+
+    /// Branch by the offset at `addr` if `taken`, returning the extra cycles that took: one for
+    /// taking it, and another if it lands on a different page
+    fn branch(&mut self, bus: &mut impl Bus, addr: u16, taken: bool) -> usize {
+        let offset = bus.read(addr) as i8;
+
+        if !taken {
+            return 0;
+        }
+
+        let target = self.pc.wrapping_add_signed(i16::from(offset));
+        let cycles = if target & 0xFF00 != self.pc & 0xFF00 {
+            2
+        } else {
+            1
+        };
+
+        self.pc = target;
+
+        cycles
+    }
+
+    fn push(&mut self, bus: &mut impl Bus, val: u8) {
+        bus.write(STACK_BASE | u16::from(self.sp), val);
+        self.sp = self.sp.wrapping_sub(1);
+    }
+
+    fn push_pc(&mut self, bus: &mut impl Bus) {
+        let [lo, hi] = self.pc.to_le_bytes();
+        self.push(bus, hi);
+        self.push(bus, lo);
+    }
+
+    fn pop(&mut self, bus: &mut impl Bus) -> u8 {
+        self.sp = self.sp.wrapping_add(1);
+        bus.read(STACK_BASE | u16::from(self.sp))
+    }
+
+    fn pop_u16(&mut self, bus: &mut impl Bus) -> u16 {
+        let lo = self.pop(bus);
+        let hi = self.pop(bus);
+
+        u16::from_le_bytes([lo, hi])
+    }
+}
+
+fn indexed(base: u16, index: u8) -> (u16, bool) {
+    let addr = base.wrapping_add(u16::from(index));
+
+    (addr, base & 0xFF00 != addr & 0xFF00)
+}
+
+fn read_u16(bus: &mut impl Bus, addr: u16) -> u16 {
+    u16::from_le_bytes([bus.read(addr), bus.read(addr.wrapping_add(1))])
+}
+
+/// A pointer in the zero page, whose high byte wraps around to $00
+fn read_zero_page_u16(bus: &mut impl Bus, pointer: u8) -> u16 {
+    u16::from_le_bytes([
+        bus.read(u16::from(pointer)),
+        bus.read(u16::from(pointer.wrapping_add(1))),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 64K of RAM that records every write
+    struct TestBus {
+        memory: Vec<u8>,
+        writes: Vec<(u16, u8)>,
+    }
+
+    impl Bus for TestBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.memory[usize::from(addr)]
+        }
+
+        fn write(&mut self, addr: u16, val: u8) {
+            self.memory[usize::from(addr)] = val;
+            self.writes.push((addr, val));
+        }
+    }
+
+    /// A CPU about to run `code` at $8000
+    fn setup(code: &[u8]) -> (Cpu, TestBus) {
+        let mut memory = vec![0; 0x10000];
+        memory[0x8000..0x8000 + code.len()].copy_from_slice(code);
+
+        let cpu = Cpu {
+            pc: 0x8000,
+            ..Cpu::new()
+        };
+
+        (
+            cpu,
+            TestBus {
+                memory,
+                writes: vec![],
+            },
+        )
+    }
+
+    #[test]
+    fn adc_sets_carry_and_overflow() {
+        // LDA #$50; ADC #$50; ADC #$C0
+        let (mut cpu, mut bus) = setup(&[0xA9, 0x50, 0x69, 0x50, 0x69, 0xC0]);
+
+        cpu.step(&mut bus).unwrap();
+        cpu.step(&mut bus).unwrap();
+        assert_eq!(cpu.acc, 0xA0);
+        assert!(cpu.status.contains(Status::V | Status::N));
+        assert!(!cpu.status.contains(Status::C));
+
+        cpu.step(&mut bus).unwrap();
+        assert_eq!(cpu.acc, 0x60);
+        assert!(cpu.status.contains(Status::C | Status::V));
+    }
+
+    #[test]
+    fn sbc_borrows_when_carry_is_clear() {
+        // SEC; LDA #$10; SBC #$20; SBC #$01
+        let (mut cpu, mut bus) = setup(&[0x38, 0xA9, 0x10, 0xE9, 0x20, 0xE9, 0x01]);
+
+        for _ in 0..3 {
+            cpu.step(&mut bus).unwrap();
+        }
+        assert_eq!(cpu.acc, 0xF0);
+        assert!(!cpu.status.contains(Status::C));
+
+        cpu.step(&mut bus).unwrap();
+        assert_eq!(cpu.acc, 0xEE);
+    }
+
+    #[test]
+    fn reads_take_a_cycle_longer_when_indexing_crosses_a_page() {
+        // LDX #$01; LDA $80FF,X; LDA $8000,X; STA $80FF,X
+        let (mut cpu, mut bus) = setup(&[
+            0xA2, 0x01, 0xBD, 0xFF, 0x80, 0xBD, 0x00, 0x80, 0x9D, 0xFF, 0x80,
+        ]);
+
+        assert_eq!(cpu.step(&mut bus), Ok(2));
+        assert_eq!(cpu.step(&mut bus), Ok(5));
+        assert_eq!(cpu.step(&mut bus), Ok(4));
+        assert_eq!(cpu.step(&mut bus), Ok(5));
+    }
+
+    #[test]
+    fn branches_take_a_cycle_to_take_and_another_to_cross_a_page() {
+        // BNE +2 (not taken, Z is set by LDA #0)
+        let (mut cpu, mut bus) = setup(&[0xA9, 0x00, 0xD0, 0x02, 0xF0, 0x02]);
+
+        cpu.step(&mut bus).unwrap();
+        assert_eq!(cpu.step(&mut bus), Ok(2));
+        assert_eq!(cpu.pc, 0x8004);
+
+        // BEQ +2, taken
+        assert_eq!(cpu.step(&mut bus), Ok(3));
+        assert_eq!(cpu.pc, 0x8008);
+
+        // BEQ -10, back across to $7FFE
+        bus.memory[0x8008..0x800A].copy_from_slice(&[0xF0, 0xF4]);
+        assert_eq!(cpu.step(&mut bus), Ok(4));
+        assert_eq!(cpu.pc, 0x7FFE);
+    }
+
+    #[test]
+    fn indirect_jmp_doesnt_cross_pages() {
+        // JMP ($10FF)
+        let (mut cpu, mut bus) = setup(&[0x6C, 0xFF, 0x10]);
+        bus.memory[0x10FF] = 0x34;
+        bus.memory[0x1000] = 0x12;
+        bus.memory[0x1100] = 0x56;
+
+        assert_eq!(cpu.step(&mut bus), Ok(5));
+        assert_eq!(cpu.pc, 0x1234);
+    }
+
+    #[test]
+    fn jsr_and_rts_return_after_the_jsr() {
+        // JSR $9000 ... $9000: RTS
+        let (mut cpu, mut bus) = setup(&[0x20, 0x00, 0x90]);
+        bus.memory[0x9000] = 0x60;
+
+        assert_eq!(cpu.step(&mut bus), Ok(6));
+        assert_eq!(cpu.pc, 0x9000);
+        assert_eq!(cpu.sp, 0xFB);
+
+        assert_eq!(cpu.step(&mut bus), Ok(6));
+        assert_eq!(cpu.pc, 0x8003);
+        assert_eq!(cpu.sp, 0xFD);
+    }
+
+    #[test]
+    fn brk_pushes_b_but_interrupts_dont() {
+        // BRK, with the IRQ vector at $9000, where there's an RTI
+        let (mut cpu, mut bus) = setup(&[0x00]);
+        bus.memory[0xFFFE..].copy_from_slice(&[0x00, 0x90]);
+        bus.memory[0x9000] = 0x40;
+        cpu.status = Status::U | Status::C;
+
+        assert_eq!(cpu.step(&mut bus), Ok(7));
+        assert_eq!(cpu.pc, 0x9000);
+        assert_eq!(bus.writes, [(0x01FD, 0x80), (0x01FC, 0x02), (0x01FB, 0x31)]);
+        assert!(cpu.status.contains(Status::I));
+
+        cpu.step(&mut bus).unwrap();
+        assert_eq!(cpu.pc, 0x8002);
+        assert_eq!(cpu.status, Status::U | Status::C);
+
+        bus.writes.clear();
+        assert_eq!(cpu.interrupt(&mut bus, 0x9000), 7);
+        assert_eq!(bus.writes, [(0x01FD, 0x80), (0x01FC, 0x02), (0x01FB, 0x21)]);
+        assert_eq!(cpu.pc, 0x9000);
+    }
+
+    #[test]
+    fn jam_opcodes_stop_the_cpu() {
+        let (mut cpu, mut bus) = setup(&[0xEA, 0x02]);
+
+        assert_eq!(cpu.step(&mut bus), Ok(2));
+        assert_eq!(
+            cpu.step(&mut bus),
+            Err(Jammed {
+                pc: 0x8001,
+                opcode: 0x02
+            })
+        );
+        assert_eq!(cpu.pc, 0x8001);
+    }
+
+    /// Run `code` at $8000 for `steps` instructions, returning the writes it made
+    fn writes(code: &[u8], steps: usize) -> Vec<(u16, u8)> {
+        let (mut cpu, mut bus) = setup(code);
+
+        for _ in 0..steps {
+            cpu.step(&mut bus).unwrap();
+        }
+
+        bus.writes
+    }
+
+    #[test]
+    fn sta_writes_once() {
+        // LDA #$42; STA $1234
+        assert_eq!(writes(&[0xA9, 0x42, 0x8D, 0x34, 0x12], 2), [(0x1234, 0x42)]);
+    }
+
+    #[test]
+    fn read_modify_write_writes_the_old_value_first() {
+        // INC $10, with $41 there
+        let (mut cpu, mut bus) = setup(&[0xE6, 0x10]);
+        bus.memory[0x10] = 0x41;
+
+        assert_eq!(cpu.step(&mut bus), Ok(5));
+        assert_eq!(bus.writes, [(0x0010, 0x41), (0x0010, 0x42)]);
+
+        // ASL A touches no memory
+        assert_eq!(writes(&[0x0A], 1), []);
+    }
+
+    #[test]
+    fn pushes_write_down_the_stack() {
+        // LDA #$99; PHA; JSR $9000
+        assert_eq!(
+            writes(&[0xA9, 0x99, 0x48, 0x20, 0x00, 0x90], 3),
+            [(0x01FD, 0x99), (0x01FC, 0x80), (0x01FB, 0x05)]
+        );
+    }
+
+    #[test]
+    fn shx_and_shy_mangle_the_address_on_a_page_cross() {
+        // LDX #$FF; LDY #$01; SHX $1200,Y: $FF & ($12 + 1) to $1201
+        assert_eq!(
+            writes(&[0xA2, 0xFF, 0xA0, 0x01, 0x9E, 0x00, 0x12], 3),
+            [(0x1201, 0x13)]
+        );
+
+        // LDX #$05; LDY #$FF; SHX $12FF,Y: $05 & $13 to $13FE, with its high byte replaced
+        assert_eq!(
+            writes(&[0xA2, 0x05, 0xA0, 0xFF, 0x9E, 0xFF, 0x12], 3),
+            [(0x01FE, 0x01)]
+        );
+
+        // LDY #$0F; LDX #$02; SHY $12FF,X: $0F & $13 to $1301, with its high byte replaced
+        assert_eq!(
+            writes(&[0xA0, 0x0F, 0xA2, 0x02, 0x9C, 0xFF, 0x12], 3),
+            [(0x0301, 0x03)]
+        );
+    }
+}
@@ -0,0 +1,443 @@
+use anyhow::anyhow;
+
+use std::mem;
+
+use crate::apu::Apu;
+use crate::cpu::{Bus, Cpu, Status};
+use crate::expansion::ExpansionOptions;
+use crate::nsf::{
+    irq::IrqTimer, memory::NsfMemory, Lint, NsfHeader, IRQ_FLAG, NON_RETURNING_INIT_FLAG,
+    NO_PLAY_FLAG,
+};
+use crate::region::Region;
+
+/// The player's own code, at $4100, in open bus where no NSF can put anything:
+///
+/// $4100    LDA #song       The song to play, counting from 0
+/// $4102    LDX #region     0 for NTSC, 1 for PAL
+/// $4104    JSR INIT
+/// $4107    JMP $4107       Idle until it's time for PLAY
+/// $410A    JSR PLAY
+/// $410D    JMP $4107
+/// $4110    JSR PLAY        The NMI handler, for tunes whose INIT doesn't return
+/// $4113    RTI
+///
+/// The CPU runs it like any other code. INIT and PLAY returning is how it gets back to the
+/// idle loop, where the driver sends it on to PLAY again when it's time.
+mod trampoline {
+    pub const START: u16 = 0x4100;
+    pub const IDLE: u16 = 0x4107;
+    pub const JSR_PLAY: u16 = 0x410A;
+    pub const NMI: u16 = 0x4110;
+    pub const RTI: u16 = 0x4113;
+    pub const LEN: usize = 0x14;
+}
+
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+/// The trampoline's code for playing `song_index` of the NSF in `region`
+#[rustfmt::skip]
+fn trampoline_code(header: &NsfHeader, song_index: u8, region: Region) -> [u8; trampoline::LEN] {
+    let [init_lo, init_hi] = header.init_address.to_le_bytes();
+    let [play_lo, play_hi] = header.play_address.to_le_bytes();
+    let [idle_lo, idle_hi] = trampoline::IDLE.to_le_bytes();
+
+    [
+        0xA9, song_index,                       // LDA #song
+        0xA2, u8::from(region != Region::Ntsc), // LDX #region
+        0x20, init_lo, init_hi,                 // JSR INIT
+        0x4C, idle_lo, idle_hi,                 // JMP IDLE
+        0x20, play_lo, play_hi,                 // JSR PLAY
+        0x4C, idle_lo, idle_hi,                 // JMP IDLE
+        0x20, play_lo, play_hi,                 // JSR PLAY
+        0x40,                                   // RTI
+    ]
+}
+
+/// Everything the CPU sees while it plays an NSF:
+///
+/// $0000-$1FFF    2K of RAM, mirrored
+/// $4000-$4017    The APU
+/// $401B-$401D    The IRQ timer, for NSF2 tunes with the IRQ flag set
+/// $4100-$4113    The trampoline
+/// $4020-$FFFF    The expansion chips' registers, and the NSF's own memory (see `NsfMemory`)
+///
+/// Every write to $4020-$FFFF goes to both the APU, for the expansion chips, and the NSF's
+/// memory, since the VRC6's and others' registers sit on top of ROM. Reading an address nothing
+/// answers gives the last value on the bus.
+struct NsfBus {
+    ram: [u8; 0x800],
+    apu: Apu,
+    irq_timer: Option<IrqTimer>,
+    trampoline: [u8; trampoline::LEN],
+    memory: NsfMemory,
+    open_bus: u8,
+}
+
+impl Bus for NsfBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        let trampoline_end = trampoline::START + trampoline::LEN as u16;
+
+        let val = match addr {
+            0x0000..=0x1FFF => Some(self.ram[usize::from(addr & 0x07FF)]),
+            0x4015 => Some(self.apu.read_status()),
+            _ if (trampoline::START..trampoline_end).contains(&addr) => {
+                Some(self.trampoline[usize::from(addr - trampoline::START)])
+            }
+            0x4020..=0xFFFF => self.memory.read(addr).or_else(|| self.apu.read(addr)),
+            _ => None,
+        }
+        .unwrap_or(self.open_bus);
+
+        self.open_bus = val;
+
+        val
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        self.open_bus = val;
+
+        match addr {
+            0x0000..=0x1FFF => self.ram[usize::from(addr & 0x07FF)] = val,
+            0x4000..=0x4017 => self.apu.write(addr, val),
+            0x401B..=0x401D => {
+                if let Some(irq_timer) = &mut self.irq_timer {
+                    irq_timer.write(addr, val);
+                }
+            }
+            0x4020..=0xFFFF => {
+                self.apu.write(addr, val);
+
+                if let Some(addrs) = self.memory.write(addr, val) {
+                    self.apu
+                        .load_dmc_memory(*addrs.start(), self.memory.slice(addrs));
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Runs an NSF's program on our own 6502 (see `Cpu`), against RAM, the APU with the tune's
+/// expansion chips, and an `NsfMemory` holding its data.
+///
+/// PLAY is called from the trampoline (see `trampoline`) every `play_speed_ntsc` or
+/// `play_speed_pal` microseconds, once INIT and the previous PLAY have returned. NSF2 tunes can
+/// change that:
+/// - with a non-returning INIT, PLAY is called from an NMI handler instead, interrupting
+///   whatever INIT is still doing
+/// - with PLAY suppressed, the CPU idles once INIT returns, and only IRQs run code
+/// - with the IRQ flag set, the IRQ timer at $401B-$401D is available and the IRQ vector at
+///   $FFFE-$FFFF is writable
+///
+/// The APU's own IRQs (the frame counter's and the DMC's) are taken too.
+pub struct NsfDriver {
+    header: NsfHeader,
+    region: Region,
+    cpu: Cpu,
+    bus: NsfBus,
+    /// CPU cycles between PLAY calls
+    play_period: f64,
+    cycles_until_play: f64,
+    play_pending: bool,
+    /// Set while the NMI handler is running
+    in_nmi: bool,
+    samples: Vec<f64>,
+}
+
+impl NsfDriver {
+    /// A driver set up to play `track` (counting from 1) of the NSF, producing `sample_rate`
+    /// samples a second, with its expansion chips set up with `options`
+    pub fn new(
+        header: NsfHeader,
+        data: &[u8],
+        track: u8,
+        sample_rate: f64,
+        options: &ExpansionOptions,
+    ) -> anyhow::Result<Self> {
+        let song_index = header.track_index(track).ok_or_else(|| {
+            anyhow!(
+                "there's no track {track}; tracks go from 1 to {}",
+                header.total_songs
+            )
+        })?;
+
+        let region = Region::for_nsf(&header);
+        let memory = NsfMemory::new(&header, data);
+
+        let mut apu = Apu::for_nsf(&header, sample_rate, options);
+
+        for addr in 0x4000..=0x4013 {
+            apu.write(addr, 0x0);
+        }
+
+        apu.write(0x4015, 0x0F);
+        apu.write(0x4017, 0x40);
+        apu.load_dmc_memory(0x8000, memory.prg_rom());
+
+        let bus = NsfBus {
+            ram: [0; 0x800],
+            apu,
+            irq_timer: (header.flags & IRQ_FLAG != 0).then(IrqTimer::default),
+            trampoline: trampoline_code(&header, song_index, region),
+            memory,
+            open_bus: 0,
+        };
+
+        let cpu = Cpu {
+            pc: trampoline::START,
+            ..Cpu::new()
+        };
+
+        let play_period = header.play_period(region).as_secs_f64() * region.cpu_clock_hz();
+
+        Ok(NsfDriver {
+            header,
+            region,
+            cpu,
+            bus,
+            play_period,
+            cycles_until_play: play_period,
+            play_pending: false,
+            in_nmi: false,
+            samples: vec![],
+        })
+    }
+
+    pub fn header(&self) -> &NsfHeader {
+        &self.header
+    }
+
+    /// Problems loading the program data into memory
+    pub fn warnings(&self) -> &[Lint] {
+        self.bus.memory.warnings()
+    }
+
+    /// The CPU clock, in cycles per second
+    pub fn clock_rate(&self) -> f64 {
+        self.region.cpu_clock_hz()
+    }
+
+    /// The samples produced since the last call
+    pub fn take_samples(&mut self) -> Vec<f64> {
+        mem::take(&mut self.samples)
+    }
+
+    /// Run the CPU for a single instruction (or take an interrupt), and the APU alongside it
+    pub fn step(&mut self) -> anyhow::Result<usize> {
+        let irq_line = self
+            .bus
+            .irq_timer
+            .as_ref()
+            .is_some_and(IrqTimer::irq_pending)
+            || self.bus.apu.irq_pending();
+        let irq = irq_line && !self.cpu.status.contains(Status::I);
+
+        let nmi = self.play_pending
+            && self.header.flags & NON_RETURNING_INIT_FLAG != 0
+            && self.header.flags & NO_PLAY_FLAG == 0
+            && !self.in_nmi;
+
+        let cycles = if nmi {
+            self.play_pending = false;
+            self.in_nmi = true;
+            self.cpu.interrupt(&mut self.bus, trampoline::NMI)
+        } else if irq {
+            let vector =
+                u16::from_le_bytes([self.bus.read(IRQ_VECTOR), self.bus.read(IRQ_VECTOR + 1)]);
+            self.cpu.interrupt(&mut self.bus, vector)
+        } else {
+            match self.cpu.pc {
+                trampoline::IDLE if self.play_pending && self.header.flags & NO_PLAY_FLAG == 0 => {
+                    self.play_pending = false;
+                    self.cpu.pc = trampoline::JSR_PLAY;
+                }
+                trampoline::RTI => self.in_nmi = false,
+                _ => (),
+            }
+
+            self.cpu.step(&mut self.bus)?
+        };
+
+        if let Some(irq_timer) = &mut self.bus.irq_timer {
+            irq_timer.clock(cycles);
+        }
+
+        self.cycles_until_play -= cycles as f64;
+
+        if self.cycles_until_play <= 0.0 {
+            self.cycles_until_play += self.play_period;
+            self.play_pending = true;
+        }
+
+        self.bus.apu.run(cycles, &mut self.samples);
+
+        Ok(cycles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nsf::read_nsf;
+
+    const SAMPLE_RATE: f64 = 44_100.0;
+
+    /// The RAM byte the test programs count PLAY calls and IRQs in
+    const COUNTER: usize = 0x00;
+
+    /// A three-track NSF2 header with `flags`, loading and initialising at $8000 and playing at
+    /// $8010, in `pal_ntsc_bits`' region
+    fn header(flags: u8, pal_ntsc_bits: u8) -> NsfHeader {
+        let mut nsf = b"NESM\x1a\x02\x03\x01\x00\x80\x00\x80\x10\x80".to_vec();
+        nsf.resize(0x80, 0);
+        nsf[0x06E..0x070].copy_from_slice(&16639u16.to_le_bytes());
+        nsf[0x078..0x07A].copy_from_slice(&19997u16.to_le_bytes());
+        nsf[0x07A] = pal_ntsc_bits;
+        nsf[0x07C] = flags;
+
+        read_nsf(&nsf).unwrap().0
+    }
+
+    /// Program data with `init` at $8000 and a PLAY at $8010 that counts its calls
+    fn data(init: &[u8]) -> Vec<u8> {
+        let mut data = init.to_vec();
+        data.resize(0x10, 0);
+        // INC $00; RTS
+        data.extend_from_slice(&[0xE6, COUNTER as u8, 0x60]);
+
+        data
+    }
+
+    fn new_driver(header: NsfHeader, data: &[u8]) -> NsfDriver {
+        NsfDriver::new(header, data, 1, SAMPLE_RATE, &ExpansionOptions::default()).unwrap()
+    }
+
+    /// Run `driver` for at least `cycles` CPU cycles
+    fn run(driver: &mut NsfDriver, cycles: f64) {
+        let mut ran = 0;
+
+        while (ran as f64) < cycles {
+            ran += driver.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn trampoline() {
+        let header = header(0, 0b01);
+        let mut driver = NsfDriver::new(
+            header,
+            &data(&[0x60]),
+            2,
+            SAMPLE_RATE,
+            &ExpansionOptions::default(),
+        )
+        .unwrap();
+
+        let code: Vec<_> = (0x4100..0x4114).map(|addr| driver.bus.read(addr)).collect();
+
+        assert_eq!(
+            code,
+            [
+                0xA9, 0x01, // LDA #1
+                0xA2, 0x01, // LDX #1 (PAL)
+                0x20, 0x00, 0x80, // JSR $8000
+                0x4C, 0x07, 0x41, // JMP $4107
+                0x20, 0x10, 0x80, // JSR $8010
+                0x4C, 0x07, 0x41, // JMP $4107
+                0x20, 0x10, 0x80, // JSR $8010
+                0x40, // RTI
+            ]
+        );
+        assert_eq!(driver.cpu.pc, trampoline::START);
+    }
+
+    #[test]
+    fn play_is_called_once_per_play_period() {
+        for pal_ntsc_bits in [0b00, 0b01] {
+            let mut driver = new_driver(header(0, pal_ntsc_bits), &data(&[0x60]));
+            let play_period = driver.play_period;
+
+            run(&mut driver, 10.5 * play_period);
+
+            assert_eq!(
+                driver.bus.ram[COUNTER], 10,
+                "region bits {pal_ntsc_bits:#04b}"
+            );
+        }
+
+        let ntsc = new_driver(header(0, 0b00), &[]);
+        let pal = new_driver(header(0, 0b01), &[]);
+
+        assert_eq!(ntsc.play_period.round(), 29780.0);
+        assert_eq!(pal.play_period.round(), 33247.0);
+    }
+
+    #[test]
+    fn non_returning_init_gets_play_as_an_nmi() {
+        // JMP $8000
+        let mut driver = new_driver(
+            header(NON_RETURNING_INIT_FLAG, 0),
+            &data(&[0x4C, 0x00, 0x80]),
+        );
+        let play_period = driver.play_period;
+
+        run(&mut driver, 10.5 * play_period);
+
+        assert_eq!(driver.bus.ram[COUNTER], 10);
+        assert!(!driver.in_nmi);
+    }
+
+    #[test]
+    fn no_play_flag() {
+        let mut driver = new_driver(header(NO_PLAY_FLAG, 0), &data(&[0x60]));
+        let play_period = driver.play_period;
+
+        run(&mut driver, 3.5 * play_period);
+
+        assert_eq!(driver.bus.ram[COUNTER], 0);
+        assert_eq!(driver.cpu.pc, trampoline::IDLE);
+    }
+
+    #[test]
+    fn irq_timer() {
+        #[rustfmt::skip]
+        let init = [
+            0xA9, 0x20, 0x8D, 0xFE, 0xFF, // LDA #$20; STA $FFFE
+            0xA9, 0x80, 0x8D, 0xFF, 0xFF, // LDA #$80; STA $FFFF
+            0xA9, 0xE7, 0x8D, 0x1B, 0x40, // LDA #$E7; STA $401B
+            0xA9, 0x03, 0x8D, 0x1C, 0x40, // LDA #$03; STA $401C: 1000 cycles
+            0xA9, 0x01, 0x8D, 0x1D, 0x40, // LDA #$01; STA $401D
+            0x58,                         // CLI
+            0x60,                         // RTS
+        ];
+        #[rustfmt::skip]
+        let handler = [
+            0xE6, COUNTER as u8,          // INC $00
+            0x8D, 0x1D, 0x40,             // STA $401D
+            0x40,                         // RTI
+        ];
+
+        let mut data = init.to_vec();
+        data.resize(0x20, 0);
+        data.extend_from_slice(&handler);
+
+        let mut driver = new_driver(header(IRQ_FLAG | NO_PLAY_FLAG, 0), &data);
+
+        run(&mut driver, 10_500.0);
+
+        // Each acknowledgement restarts the count a few cycles late
+        assert!(
+            (9..=10).contains(&driver.bus.ram[COUNTER]),
+            "{} IRQs",
+            driver.bus.ram[COUNTER]
+        );
+
+        // Without the IRQ flag there's no timer, and the vector can't be written
+        let mut driver = new_driver(header(NO_PLAY_FLAG, 0), &data);
+
+        run(&mut driver, 10_500.0);
+
+        assert_eq!(driver.bus.ram[COUNTER], 0);
+    }
+}
@@ -0,0 +1,106 @@
+use bitflags::bitflags;
+use std::fmt::Debug;
+
+use crate::region::Region;
+
+use self::fds::Fds;
+use self::mmc5::Mmc5;
+use self::namco163::{Mixing, Namco163};
+use self::sunsoft5b::Sunsoft5b;
+use self::vrc6::Vrc6;
+use self::vrc7::Vrc7;
+
+pub mod fds;
+pub mod mmc5;
+pub mod namco163;
+pub mod sunsoft5b;
+pub mod vrc6;
+pub mod vrc7;
+
+bitflags! {
+    /// The expansion chips an NSF uses: the bits of its header's `sound_chip_support` byte
+    #[derive(Default)]
+    pub struct ExpansionChips: u8 {
+        const VRC6 = 0b0000_0001;
+        const VRC7 = 0b0000_0010;
+        const FDS = 0b0000_0100;
+        const MMC5 = 0b0000_1000;
+        const NAMCO163 = 0b0001_0000;
+        const SUNSOFT5B = 0b0010_0000;
+        /// Not emulated
+        const VT02 = 0b0100_0000;
+    }
+}
+
+/// A sound chip on the cartridge whose output is mixed in with the 2A03's
+pub trait ExpansionAudio: Debug {
+    /// A CPU write to anywhere in $4020-$FFFF. Addresses that aren't one of the chip's
+    /// registers are ignored.
+    fn write(&mut self, addr: u16, val: u8);
+
+    /// A CPU read of one of the chip's registers, or `None` if it doesn't respond to `addr`
+    fn read(&mut self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    /// Run the chip for a single CPU cycle
+    fn clock(&mut self);
+
+    /// The chip's current output, scaled to sit at the right level against the 2A03 mixer's
+    /// 0.0 to 1.0 output
+    fn output(&self) -> f64;
+}
+
+/// Settings for the chips that have any
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExpansionOptions {
+    /// How loud a single Sunsoft 5B channel at full volume is, in 2A03 pulses at volume 15
+    pub sunsoft5b_mix_level: f64,
+    /// How the Namco 163's channels are combined
+    pub namco163_mixing: Mixing,
+}
+
+impl Default for ExpansionOptions {
+    fn default() -> Self {
+        ExpansionOptions {
+            sunsoft5b_mix_level: 1.0,
+            namco163_mixing: Mixing::default(),
+        }
+    }
+}
+
+/// The chips in `expansion_chips` that are emulated, set up with `options` and ready to be
+/// added to an `Apu`
+pub fn from_expansion_chips(
+    expansion_chips: ExpansionChips,
+    region: Region,
+    options: &ExpansionOptions,
+) -> Vec<Box<dyn ExpansionAudio>> {
+    let mut chips: Vec<Box<dyn ExpansionAudio>> = vec![];
+
+    if expansion_chips.contains(ExpansionChips::VRC6) {
+        chips.push(Box::new(Vrc6::default()));
+    }
+
+    if expansion_chips.contains(ExpansionChips::VRC7) {
+        chips.push(Box::new(Vrc7::default()));
+    }
+
+    if expansion_chips.contains(ExpansionChips::FDS) {
+        chips.push(Box::new(Fds::new(region)));
+    }
+
+    if expansion_chips.contains(ExpansionChips::MMC5) {
+        chips.push(Box::new(Mmc5::default()));
+    }
+
+    if expansion_chips.contains(ExpansionChips::NAMCO163) {
+        chips.push(Box::new(Namco163::new(options.namco163_mixing)));
+    }
+
+    if expansion_chips.contains(ExpansionChips::SUNSOFT5B) {
+        chips.push(Box::new(Sunsoft5b::new(options.sunsoft5b_mix_level)));
+    }
+
+    chips
+}
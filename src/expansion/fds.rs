@@ -0,0 +1,426 @@
+use super::ExpansionAudio;
+use crate::filter::OnePole;
+use crate::region::Region;
+
+/// At full volume, the FDS channel is about 2.4 times as loud as a 2A03 pulse at volume 15
+const OUTPUT_SCALE: f64 = 2.4 * 95.88 / (8128.0 / 15.0 + 100.0) / (63.0 * 32.0);
+
+/// The FDS output goes through an RC low-pass at roughly 2 kHz
+const LOW_PASS_HZ: f64 = 2000.0;
+
+/// Master volume from $4089: 2/2, 2/3, 2/4 and 2/5 of full scale
+const MASTER_VOLUMES: [f64; 4] = [1.0, 2.0 / 3.0, 2.0 / 4.0, 2.0 / 5.0];
+
+/// What each 3-bit modulation table entry does to the modulation counter; `None` resets it
+const MOD_ADJUSTMENTS: [Option<i8>; 8] = [
+    Some(0),
+    Some(1),
+    Some(2),
+    Some(4),
+    None,
+    Some(-4),
+    Some(-2),
+    Some(-1),
+];
+
+/// A volume or modulation envelope
+///
+/// MDVV VVVV    Envelope off (M, 1 = use V as the gain directly), direction (D, 1 = increase), speed or gain (V)
+#[derive(Debug, Clone, Default)]
+struct Envelope {
+    off: bool,
+    increase: bool,
+    speed: u8,
+    gain: u8,
+    timer: u32,
+}
+
+impl Envelope {
+    fn write(&mut self, val: u8) {
+        self.off = val >> 7 & 0b1 != 0;
+        self.increase = val >> 6 & 0b1 != 0;
+        self.speed = val & 0b11_1111;
+        self.timer = 0;
+
+        if self.off {
+            self.gain = self.speed;
+        }
+    }
+
+    /// Ticks every 8 * (master speed + 1) * (speed + 1) CPU cycles
+    fn clock(&mut self, master_speed: u8) {
+        if self.off {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = 8 * (u32::from(master_speed) + 1) * (u32::from(self.speed) + 1) - 1;
+
+        if self.increase {
+            if self.gain < 32 {
+                self.gain += 1;
+            }
+        } else if self.gain > 0 {
+            self.gain -= 1;
+        }
+    }
+}
+
+/// The Famicom Disk System's sound channel: a 64-step, 6-bit wavetable whose pitch can be
+/// swept by a second, 64-step modulation table
+///
+/// $4040-$407F    Wavetable RAM (writable while $4089 bit 7 is set)
+/// $4080          Volume envelope
+/// $4082          Wave frequency low
+/// $4083          EH-- FFFF    Halt wave and reset its phase (E), halt envelopes (H), frequency high (F)
+/// $4084          Modulation envelope
+/// $4085          -CCC CCCC    Modulation counter (7-bit signed)
+/// $4086          Modulation frequency low
+/// $4087          H--- FFFF    Halt modulation, allowing table writes (H), frequency high (F)
+/// $4088          ---- -MMM    Append an entry to the modulation table
+/// $4089          W--- --VV    Wavetable write enable (W), master volume (V)
+/// $408A          Envelope speed
+/// $4090          Read: volume gain
+/// $4092          Read: modulation gain
+#[derive(Debug, Clone)]
+pub struct Fds {
+    wavetable: [u8; 64],
+    wave_write_enabled: bool,
+    wave_halted: bool,
+    wave_frequency: u16,
+    wave_accumulator: u32,
+    /// The wave output only updates while the wavetable isn't being written to
+    wave_output: u8,
+    envelopes_halted: bool,
+    envelope_speed: u8,
+    volume: Envelope,
+    modulation: Envelope,
+    mod_table: [u8; 64],
+    /// Where the next $4088 write lands, which is also the step being played
+    mod_position: usize,
+    mod_halted: bool,
+    mod_frequency: u16,
+    mod_accumulator: u16,
+    mod_counter: i8,
+    master_volume: u8,
+    low_pass: OnePole,
+    output: f64,
+}
+
+impl Fds {
+    pub fn new(region: Region) -> Self {
+        Fds {
+            wavetable: [0; 64],
+            wave_write_enabled: false,
+            wave_halted: true,
+            wave_frequency: 0,
+            wave_accumulator: 0,
+            wave_output: 0,
+            envelopes_halted: true,
+            envelope_speed: 0xE8,
+            volume: Envelope::default(),
+            modulation: Envelope::default(),
+            mod_table: [0; 64],
+            mod_position: 0,
+            mod_halted: true,
+            mod_frequency: 0,
+            mod_accumulator: 0,
+            mod_counter: 0,
+            master_volume: 0,
+            low_pass: OnePole::low_pass(region.cpu_clock_hz(), LOW_PASS_HZ),
+            output: 0.0,
+        }
+    }
+
+    /// Sign-extend the 7-bit modulation counter
+    fn set_mod_counter(&mut self, val: u8) {
+        self.mod_counter = ((val & 0b111_1111) << 1) as i8 >> 1;
+    }
+
+    /// The wave frequency, bent by the modulation counter and gain
+    fn modulated_frequency(&self) -> u32 {
+        let pitch = i32::from(self.wave_frequency);
+        let counter = i32::from(self.mod_counter);
+
+        let mut temp = counter * i32::from(self.modulation.gain);
+        let remainder = temp & 0xF;
+        temp >>= 4;
+
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if counter < 0 { -1 } else { 2 };
+        }
+
+        // Wrap if the range is exceeded
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+
+        temp *= pitch;
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+
+        if remainder >= 32 {
+            temp += 1;
+        }
+
+        (pitch + temp).max(0) as u32
+    }
+
+    fn clock_modulation(&mut self) {
+        if self.mod_halted {
+            return;
+        }
+
+        let (accumulator, overflowed) = self.mod_accumulator.overflowing_add(self.mod_frequency);
+        self.mod_accumulator = accumulator;
+
+        // The table steps every time the 16-bit accumulator overflows
+        if overflowed {
+            match MOD_ADJUSTMENTS[self.mod_table[self.mod_position] as usize] {
+                // The counter wraps within 7 bits
+                Some(adjustment) => {
+                    self.set_mod_counter(self.mod_counter.wrapping_add(adjustment) as u8)
+                }
+                None => self.mod_counter = 0,
+            }
+
+            self.mod_position = (self.mod_position + 1) % 64;
+        }
+    }
+
+    fn clock_wave(&mut self) {
+        if self.wave_halted {
+            return;
+        }
+
+        // 22-bit accumulator; the top 6 bits index the wavetable
+        self.wave_accumulator = (self.wave_accumulator + self.modulated_frequency()) & 0x3F_FFFF;
+
+        if !self.wave_write_enabled {
+            self.wave_output = self.wavetable[(self.wave_accumulator >> 16) as usize];
+        }
+    }
+
+    fn raw_output(&self) -> f64 {
+        let gain = self.volume.gain.min(32);
+
+        f64::from(self.wave_output) * f64::from(gain) * MASTER_VOLUMES[self.master_volume as usize]
+    }
+}
+
+impl ExpansionAudio for Fds {
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x4040..=0x407F if self.wave_write_enabled => {
+                self.wavetable[(addr - 0x4040) as usize] = val & 0b11_1111;
+            }
+            0x4080 => self.volume.write(val),
+            0x4082 => self.wave_frequency = self.wave_frequency & 0x0F00 | u16::from(val),
+            0x4083 => {
+                self.wave_frequency = self.wave_frequency & 0x00FF | u16::from(val & 0b1111) << 8;
+                self.wave_halted = val >> 7 & 0b1 != 0;
+                self.envelopes_halted = val >> 6 & 0b1 != 0;
+
+                if self.wave_halted {
+                    self.wave_accumulator = 0;
+                }
+            }
+            0x4084 => self.modulation.write(val),
+            0x4085 => self.set_mod_counter(val),
+            0x4086 => self.mod_frequency = self.mod_frequency & 0x0F00 | u16::from(val),
+            0x4087 => {
+                self.mod_frequency = self.mod_frequency & 0x00FF | u16::from(val & 0b1111) << 8;
+                self.mod_halted = val >> 7 & 0b1 != 0;
+
+                if self.mod_halted {
+                    self.mod_accumulator = 0;
+                }
+            }
+            // Each write fills two consecutive steps of the table
+            0x4088 if self.mod_halted => {
+                self.mod_table[self.mod_position] = val & 0b111;
+                self.mod_table[(self.mod_position + 1) % 64] = val & 0b111;
+                self.mod_position = (self.mod_position + 2) % 64;
+            }
+            0x4089 => {
+                self.wave_write_enabled = val >> 7 & 0b1 != 0;
+                self.master_volume = val & 0b11;
+            }
+            0x408A => self.envelope_speed = val,
+            _ => (),
+        }
+    }
+
+    fn read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4040..=0x407F => Some(self.wavetable[(addr - 0x4040) as usize] | 0b0100_0000),
+            0x4090 => Some(self.volume.gain | 0b0100_0000),
+            0x4092 => Some(self.modulation.gain | 0b0100_0000),
+            _ => None,
+        }
+    }
+
+    fn clock(&mut self) {
+        if !self.envelopes_halted && !self.wave_halted && self.envelope_speed > 0 {
+            self.volume.clock(self.envelope_speed);
+            self.modulation.clock(self.envelope_speed);
+        }
+
+        self.clock_modulation();
+        self.clock_wave();
+
+        self.output = self.low_pass.process(self.raw_output());
+    }
+
+    fn output(&self) -> f64 {
+        self.output * OUTPUT_SCALE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fds() -> Fds {
+        Fds::new(Region::Ntsc)
+    }
+
+    /// Clock until the modulation table moves on a step, returning the counter
+    fn step_modulation(fds: &mut Fds) -> i8 {
+        let position = fds.mod_position;
+
+        while fds.mod_position == position {
+            fds.clock();
+        }
+
+        fds.mod_counter
+    }
+
+    #[test]
+    fn wavetable_is_only_writable_while_4089_bit_7_is_set() {
+        let mut fds = fds();
+
+        fds.write(0x4040, 0x3F);
+        assert_eq!(fds.read(0x4040), Some(0x40));
+
+        fds.write(0x4089, 0x80);
+        fds.write(0x4040, 0xFF);
+        fds.write(0x407F, 0x15);
+        assert_eq!(fds.read(0x4040), Some(0x7F));
+        assert_eq!(fds.read(0x407F), Some(0x55));
+
+        fds.write(0x4089, 0x00);
+        fds.write(0x4040, 0x00);
+        assert_eq!(fds.read(0x4040), Some(0x7F));
+    }
+
+    #[test]
+    fn wave_plays_the_table_at_the_volume_gain() {
+        let mut fds = fds();
+
+        fds.write(0x4089, 0x80);
+        for addr in 0x4040..=0x405F {
+            fds.write(addr, 0x3F);
+        }
+        fds.write(0x4089, 0x00);
+
+        // Gain 32 directly, and the highest frequency so every step is visited
+        fds.write(0x4080, 0x80 | 32);
+        fds.write(0x4082, 0xFF);
+        fds.write(0x4083, 0x0F);
+
+        // 16 cycles a step
+        let outputs: Vec<u8> = (0..1024)
+            .map(|_| {
+                fds.clock();
+                fds.wave_output
+            })
+            .collect();
+
+        assert!(outputs.contains(&0x3F));
+        assert!(outputs.contains(&0));
+        assert_eq!(fds.read(0x4090), Some(0x40 | 32));
+        assert!(fds.output() > 0.0);
+    }
+
+    #[test]
+    fn wave_output_holds_while_the_table_is_writable() {
+        let mut fds = fds();
+
+        fds.write(0x4089, 0x80);
+        for addr in 0x4040..=0x407F {
+            fds.write(addr, 0x20);
+        }
+        fds.write(0x4080, 0x80 | 32);
+        fds.write(0x4082, 0xFF);
+        fds.write(0x4083, 0x0F);
+
+        for _ in 0..64 {
+            fds.clock();
+        }
+
+        assert_eq!(fds.wave_output, 0);
+    }
+
+    #[test]
+    fn modulation_table_is_only_writable_while_halted() {
+        let mut fds = fds();
+
+        fds.write(0x4087, 0x80);
+        fds.write(0x4088, 0x01);
+        fds.write(0x4088, 0x04);
+        assert_eq!(fds.mod_table[..4], [1, 1, 4, 4]);
+
+        fds.write(0x4087, 0x00);
+        fds.write(0x4088, 0x07);
+        assert_eq!(fds.mod_table[4..6], [0, 0]);
+    }
+
+    #[test]
+    fn modulation_table_steps_the_counter() {
+        let mut fds = fds();
+
+        fds.write(0x4087, 0x80);
+        fds.write(0x4085, 0x00);
+
+        // +1, +1, +2, +2, +4, +4, reset, reset, -1, -1
+        for entry in [1, 2, 3, 4, 7] {
+            fds.write(0x4088, entry);
+        }
+        assert_eq!(fds.mod_position, 10);
+
+        // Start playing from the top of the table, at the highest frequency
+        fds.mod_position = 0;
+        fds.write(0x4086, 0xFF);
+        fds.write(0x4087, 0x0F);
+
+        let counters: Vec<i8> = (0..10).map(|_| step_modulation(&mut fds)).collect();
+
+        assert_eq!(counters, [1, 2, 4, 6, 10, 14, 0, 0, -1, -2]);
+    }
+
+    #[test]
+    fn modulation_counter_wraps_within_seven_bits() {
+        let mut fds = fds();
+
+        fds.write(0x4085, 0x3F);
+        assert_eq!(fds.mod_counter, 63);
+
+        fds.write(0x4085, 0x40);
+        assert_eq!(fds.mod_counter, -64);
+
+        fds.write(0x4087, 0x80);
+        fds.write(0x4088, 0x07);
+        fds.mod_position = 0;
+        fds.write(0x4086, 0xFF);
+        fds.write(0x4087, 0x0F);
+
+        assert_eq!(step_modulation(&mut fds), 63);
+    }
+}
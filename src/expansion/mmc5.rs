@@ -0,0 +1,198 @@
+use super::ExpansionAudio;
+use crate::apu::pulse::Pulse;
+
+/// The MMC5 clocks its envelopes and length counters together at a fixed ~240 Hz instead of
+/// using a frame counter
+const FRAME_PERIOD: u16 = 7457;
+
+/// The MMC5's audio: two pulse channels like the 2A03's (minus the sweep units) and an 8-bit
+/// PCM channel
+///
+/// $5000-$5003    Pulse 1, laid out like $4000-$4003 ($5001 does nothing)
+/// $5004-$5007    Pulse 2, laid out like $4004-$4007 ($5005 does nothing)
+/// $5010          Write: I--- ---M    PCM IRQ enable (I), mode (M, 0 = write, 1 = read)
+///                Read:  I--- ----    PCM IRQ flag (I)
+/// $5011          PCM level, in write mode. Writing 0 does nothing.
+/// $5015          Write: ---- --21    Pulse enables
+///                Read:  ---- --21    Pulse length counters > 0
+///
+/// In read mode the real chip latches PCM data from the CPU's reads of $8000-$BFFF, raising
+/// the IRQ when it reads a 0. Only register reads get passed on to expansion chips, so read
+/// mode just leaves the level where it was and never raises the IRQ, so the IRQ enable is
+/// ignored and the flag always reads 0.
+#[derive(Debug, Clone)]
+pub struct Mmc5 {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    pcm_level: u8,
+    pcm_read_mode: bool,
+    even_cycle: bool,
+    frame_timer: u16,
+}
+
+impl Default for Mmc5 {
+    fn default() -> Self {
+        Mmc5 {
+            pulse1: Pulse::mmc5(),
+            pulse2: Pulse::mmc5(),
+            pcm_level: 0,
+            pcm_read_mode: false,
+            even_cycle: true,
+            frame_timer: FRAME_PERIOD,
+        }
+    }
+}
+
+impl ExpansionAudio for Mmc5 {
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x5000..=0x5003 => self.pulse1.write(addr - 0x5000, val),
+            0x5004..=0x5007 => self.pulse2.write(addr - 0x5004, val),
+            0x5010 => self.pcm_read_mode = val & 0b1 != 0,
+            0x5011 if !self.pcm_read_mode && val != 0 => self.pcm_level = val,
+            0x5015 => {
+                self.pulse1.length_counter.set_enabled(val & 0b1 != 0);
+                self.pulse2.length_counter.set_enabled(val >> 1 & 0b1 != 0);
+            }
+            _ => (),
+        }
+    }
+
+    fn read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5010 => Some(0),
+            0x5015 => Some(
+                u8::from(self.pulse2.length_counter.is_active()) << 1
+                    | u8::from(self.pulse1.length_counter.is_active()),
+            ),
+            _ => None,
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.even_cycle {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+
+        self.even_cycle = !self.even_cycle;
+
+        self.frame_timer -= 1;
+
+        if self.frame_timer == 0 {
+            self.frame_timer = FRAME_PERIOD;
+
+            for pulse in [&mut self.pulse1, &mut self.pulse2] {
+                pulse.clock_quarter_frame();
+                pulse.clock_half_frame();
+            }
+        }
+    }
+
+    /// The pulses mix like the 2A03's. The PCM channel spans twice the DMC's range at about
+    /// the same full-scale level.
+    fn output(&self) -> f64 {
+        let pulses = f64::from(self.pulse1.output() + self.pulse2.output());
+        let pcm = f64::from(self.pcm_level) / 2.0;
+
+        let pulse_out = if pulses == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulses + 100.0)
+        };
+
+        let pcm_out = if pcm == 0.0 {
+            0.0
+        } else {
+            159.79 / (22638.0 / pcm + 100.0)
+        };
+
+        pulse_out + pcm_out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pulse 1 at constant volume 15, 50% duty, with a length counter loaded
+    fn start_pulse1(mmc5: &mut Mmc5) {
+        mmc5.write(0x5000, 0b1011_1111);
+        mmc5.write(0x5002, 0x80);
+        mmc5.write(0x5003, 0x08);
+    }
+
+    #[test]
+    fn pulses_only_load_their_length_counters_while_enabled() {
+        let mut mmc5 = Mmc5::default();
+
+        start_pulse1(&mut mmc5);
+        assert_eq!(mmc5.read(0x5015), Some(0b00));
+
+        mmc5.write(0x5015, 0b11);
+        start_pulse1(&mut mmc5);
+        mmc5.write(0x5007, 0x08);
+        assert_eq!(mmc5.read(0x5015), Some(0b11));
+
+        mmc5.write(0x5015, 0b10);
+        assert_eq!(mmc5.read(0x5015), Some(0b10));
+    }
+
+    #[test]
+    fn enabled_pulse_is_audible() {
+        let mut mmc5 = Mmc5::default();
+        mmc5.write(0x5015, 0b01);
+        start_pulse1(&mut mmc5);
+
+        let peak = (0..1000)
+            .map(|_| {
+                mmc5.clock();
+                mmc5.output()
+            })
+            .fold(0.0, f64::max);
+
+        assert!(peak > 0.0);
+
+        mmc5.write(0x5015, 0b00);
+        mmc5.clock();
+        assert_eq!(mmc5.output(), 0.0);
+    }
+
+    #[test]
+    fn pcm_level_is_written_in_write_mode_except_zero() {
+        let mut mmc5 = Mmc5::default();
+
+        mmc5.write(0x5011, 0x80);
+        let level = mmc5.output();
+        assert!(level > 0.0);
+
+        mmc5.write(0x5011, 0x00);
+        assert_eq!(mmc5.output(), level);
+
+        mmc5.write(0x5011, 0xFF);
+        assert!(mmc5.output() > level);
+    }
+
+    #[test]
+    fn pcm_level_ignores_writes_in_read_mode() {
+        let mut mmc5 = Mmc5::default();
+        mmc5.write(0x5011, 0x40);
+        let level = mmc5.output();
+
+        mmc5.write(0x5010, 0b1);
+        mmc5.write(0x5011, 0xFF);
+        assert_eq!(mmc5.output(), level);
+
+        mmc5.write(0x5010, 0b0);
+        mmc5.write(0x5011, 0xFF);
+        assert!(mmc5.output() > level);
+    }
+
+    #[test]
+    fn pcm_irq_flag_reads_clear() {
+        let mut mmc5 = Mmc5::default();
+        mmc5.write(0x5010, 0x81);
+
+        assert_eq!(mmc5.read(0x5010), Some(0));
+    }
+}
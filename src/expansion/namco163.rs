@@ -0,0 +1,318 @@
+use super::ExpansionAudio;
+
+/// The chip updates one channel every 15 CPU cycles, taking turns between the enabled ones
+const CPU_CYCLES_PER_UPDATE: u8 = 15;
+
+/// A lone channel at full volume swings about as far as a 2A03 pulse at volume 15 does
+const OUTPUT_SCALE: f64 = 95.88 / (8128.0 / 15.0 + 100.0) / 225.0;
+
+/// How the channels' outputs are combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mixing {
+    /// Like the real chip: only the channel being updated is heard, switching every 15 CPU
+    /// cycles. With many channels enabled this is audible as a high-pitched whine.
+    #[default]
+    Multiplexed,
+    /// The average of all the enabled channels, without the switching noise
+    Smooth,
+}
+
+/// The Namco 163's wavetable synth: up to 8 channels playing 4-bit waveforms out of 128 bytes
+/// of internal RAM, which also holds their registers
+///
+/// $F800    ADDD DDDD    Auto-increment (A), RAM address (D)
+/// $4800    Read or write the RAM at the current address
+/// $E000    -S-- ----    Silence (S)
+///
+/// Channel registers, with channel 8 at $78-$7F and each lower channel 8 bytes below it:
+///
+/// +0    Frequency low
+/// +1    Phase low
+/// +2    Frequency middle
+/// +3    Phase middle
+/// +4    LLLL LLFF    Wave length (L, 256 - L * 4 samples), frequency high (F)
+/// +5    Phase high
+/// +6    Wave address, in 4-bit samples (low nibble first)
+/// +7    -CCC VVVV    Number of enabled channels - 1 (C, $7F only), volume (V)
+///
+/// Enabling N channels enables channels 8 down to 9 - N.
+#[derive(Debug, Clone)]
+pub struct Namco163 {
+    ram: [u8; 128],
+    address: u8,
+    auto_increment: bool,
+    silenced: bool,
+    mixing: Mixing,
+    cycle: u8,
+    /// The channel (0-7) that gets updated next
+    current_channel: usize,
+    outputs: [f64; 8],
+    output: f64,
+}
+
+impl Default for Namco163 {
+    fn default() -> Self {
+        Namco163::new(Mixing::default())
+    }
+}
+
+impl Namco163 {
+    pub fn new(mixing: Mixing) -> Self {
+        Namco163 {
+            ram: [0; 128],
+            address: 0,
+            auto_increment: false,
+            silenced: false,
+            mixing,
+            cycle: 0,
+            current_channel: 7,
+            outputs: [0.0; 8],
+            output: 0.0,
+        }
+    }
+
+    fn enabled_channels(&self) -> usize {
+        usize::from(self.ram[0x7F] >> 4 & 0b111) + 1
+    }
+
+    fn lowest_enabled_channel(&self) -> usize {
+        8 - self.enabled_channels()
+    }
+
+    fn advance_address(&mut self) {
+        if self.auto_increment {
+            self.address = (self.address + 1) & 0b111_1111;
+        }
+    }
+
+    /// The 4-bit sample at `index`, counting nibbles from the start of RAM
+    fn sample(&self, index: u8) -> u8 {
+        let byte = self.ram[usize::from(index >> 1)];
+
+        if index & 0b1 == 0 {
+            byte & 0b1111
+        } else {
+            byte >> 4
+        }
+    }
+
+    /// Step a channel's phase on by its frequency and return its new output
+    fn update_channel(&mut self, channel: usize) -> f64 {
+        let base = 0x40 + channel * 8;
+        let regs = &self.ram[base..base + 8];
+
+        let frequency =
+            u32::from(regs[4] & 0b11) << 16 | u32::from(regs[2]) << 8 | u32::from(regs[0]);
+        let phase = u32::from(regs[5]) << 16 | u32::from(regs[3]) << 8 | u32::from(regs[1]);
+        let length = 256 - u32::from(regs[4] & 0b1111_1100);
+        let wave_address = regs[6];
+        let volume = regs[7] & 0b1111;
+
+        let phase = (phase + frequency) % (length << 16);
+
+        self.ram[base + 1] = phase as u8;
+        self.ram[base + 3] = (phase >> 8) as u8;
+        self.ram[base + 5] = (phase >> 16) as u8;
+
+        let sample = self.sample(wave_address.wrapping_add((phase >> 16) as u8));
+
+        (f64::from(sample) - 8.0) * f64::from(volume)
+    }
+}
+
+impl ExpansionAudio for Namco163 {
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xF800..=0xFFFF => {
+                self.address = val & 0b111_1111;
+                self.auto_increment = val >> 7 & 0b1 != 0;
+            }
+            0x4800..=0x4FFF => {
+                self.ram[usize::from(self.address)] = val;
+                self.advance_address();
+            }
+            0xE000..=0xE7FF => self.silenced = val >> 6 & 0b1 != 0,
+            _ => (),
+        }
+    }
+
+    fn read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4800..=0x4FFF => {
+                let val = self.ram[usize::from(self.address)];
+                self.advance_address();
+
+                Some(val)
+            }
+            _ => None,
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.silenced {
+            self.output = 0.0;
+            return;
+        }
+
+        self.cycle += 1;
+
+        if self.cycle < CPU_CYCLES_PER_UPDATE {
+            return;
+        }
+
+        self.cycle = 0;
+
+        let channel = self.current_channel;
+        let lowest = self.lowest_enabled_channel();
+
+        self.outputs[channel] = self.update_channel(channel);
+
+        self.current_channel = if channel <= lowest { 7 } else { channel - 1 };
+
+        self.output = match self.mixing {
+            Mixing::Multiplexed => self.outputs[channel],
+            Mixing::Smooth => self.outputs[lowest..].iter().sum::<f64>() / (8 - lowest) as f64,
+        };
+    }
+
+    fn output(&self) -> f64 {
+        self.output * OUTPUT_SCALE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write `bytes` to RAM from `address` on, with auto-increment
+    fn write_ram(n163: &mut Namco163, address: u8, bytes: &[u8]) {
+        n163.write(0xF800, 0x80 | address);
+
+        for &byte in bytes {
+            n163.write(0x4800, byte);
+        }
+    }
+
+    /// Set `channel` (0-7) playing a 16-sample wave at RAM address 0, stepping a sample per
+    /// update
+    fn start_channel(n163: &mut Namco163, channel: u8, volume: u8, enabled_channels: u8) {
+        let length = (256 - 16) as u8;
+        let control = if channel == 7 {
+            (enabled_channels - 1) << 4 | volume
+        } else {
+            volume
+        };
+
+        write_ram(
+            n163,
+            0x40 + channel * 8,
+            &[0x00, 0, 0x00, 0, length | 0x01, 0, 0x00, control],
+        );
+    }
+
+    /// Clock until the next channel update, returning the channel that was updated
+    fn next_update(n163: &mut Namco163) -> usize {
+        let channel = n163.current_channel;
+
+        for _ in 0..CPU_CYCLES_PER_UPDATE {
+            n163.clock();
+        }
+
+        channel
+    }
+
+    #[test]
+    fn ram_reads_and_writes_auto_increment() {
+        let mut n163 = Namco163::default();
+        write_ram(&mut n163, 0x10, &[1, 2, 3]);
+
+        n163.write(0xF800, 0x80 | 0x10);
+        assert_eq!(n163.read(0x4800), Some(1));
+        assert_eq!(n163.read(0x4800), Some(2));
+
+        n163.write(0xF800, 0x12);
+        assert_eq!(n163.read(0x4800), Some(3));
+        assert_eq!(n163.read(0x4800), Some(3));
+    }
+
+    #[test]
+    fn updates_take_turns_between_the_enabled_channels() {
+        for enabled in 1..=8 {
+            let mut n163 = Namco163::default();
+            start_channel(&mut n163, 7, 0, enabled);
+
+            let updated: Vec<usize> = (0..16).map(|_| next_update(&mut n163)).collect();
+            let expected: Vec<usize> = (8 - usize::from(enabled)..8)
+                .rev()
+                .cycle()
+                .take(16)
+                .collect();
+
+            assert_eq!(updated, expected, "{enabled} channels");
+        }
+    }
+
+    #[test]
+    fn multiplexed_output_is_the_channel_just_updated() {
+        let mut n163 = Namco163::new(Mixing::Multiplexed);
+
+        // A wave that's all 15s, played by channel 8 at full volume and channel 7 silent
+        write_ram(&mut n163, 0x00, &[0xFF; 8]);
+        start_channel(&mut n163, 7, 15, 2);
+        start_channel(&mut n163, 6, 0, 2);
+
+        assert_eq!(next_update(&mut n163), 7);
+        assert_eq!(n163.output, 7.0 * 15.0);
+
+        assert_eq!(next_update(&mut n163), 6);
+        assert_eq!(n163.output, 0.0);
+    }
+
+    #[test]
+    fn smooth_output_averages_the_enabled_channels() {
+        let mut n163 = Namco163::new(Mixing::Smooth);
+
+        write_ram(&mut n163, 0x00, &[0xFF; 8]);
+        start_channel(&mut n163, 7, 15, 2);
+        start_channel(&mut n163, 6, 0, 2);
+
+        next_update(&mut n163);
+        next_update(&mut n163);
+
+        assert_eq!(n163.output, 7.0 * 15.0 / 2.0);
+    }
+
+    #[test]
+    fn channel_steps_through_its_wave() {
+        let mut n163 = Namco163::default();
+
+        // Samples 0, 1, 2, ... 15, low nibble first
+        let wave: Vec<u8> = (0..8).map(|i| ((2 * i + 1) << 4) | (2 * i)).collect();
+        write_ram(&mut n163, 0x00, &wave);
+        start_channel(&mut n163, 7, 1, 1);
+
+        let samples: Vec<f64> = (0..16)
+            .map(|_| {
+                next_update(&mut n163);
+                n163.output + 8.0
+            })
+            .collect();
+        let expected: Vec<f64> = (1..16).chain([0]).map(f64::from).collect();
+
+        assert_eq!(samples, expected);
+    }
+
+    #[test]
+    fn e000_bit_6_silences_the_chip() {
+        let mut n163 = Namco163::default();
+        write_ram(&mut n163, 0x00, &[0xFF; 8]);
+        start_channel(&mut n163, 7, 15, 1);
+        next_update(&mut n163);
+        assert!(n163.output() > 0.0);
+
+        n163.write(0xE000, 0x40);
+        n163.clock();
+
+        assert_eq!(n163.output(), 0.0);
+    }
+}
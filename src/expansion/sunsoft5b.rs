@@ -0,0 +1,416 @@
+use super::ExpansionAudio;
+
+/// Tones and noise count in steps of 16 CPU cycles; the envelope moves twice as fast, since it
+/// has 32 levels to the AY-3-8910's 16
+const CPU_CYCLES_PER_TICK: u8 = 16;
+
+/// One mix level unit is a single channel at full volume being about as loud as a 2A03 pulse
+/// at volume 15
+const OUTPUT_SCALE: f64 = 95.88 / (8128.0 / 15.0 + 100.0);
+
+/// A square tone channel
+#[derive(Debug, Clone, Copy, Default)]
+struct Tone {
+    period: u16,
+    counter: u16,
+    high: bool,
+}
+
+impl Tone {
+    /// Flips every `period` ticks, for a frequency of CPU clock / (32 * period)
+    fn tick(&mut self) {
+        self.counter += 1;
+
+        if self.counter >= self.period.max(1) {
+            self.counter = 0;
+            self.high = !self.high;
+        }
+    }
+}
+
+/// A 17-bit LFSR, stepped every other tick
+#[derive(Debug, Clone, Copy)]
+struct Noise {
+    period: u8,
+    counter: u8,
+    lfsr: u32,
+}
+
+impl Noise {
+    fn tick(&mut self) {
+        self.counter += 1;
+
+        if self.counter >= self.period.max(1) * 2 {
+            self.counter = 0;
+
+            let feedback = (self.lfsr ^ self.lfsr >> 3) & 0b1;
+            self.lfsr = self.lfsr >> 1 | feedback << 16;
+        }
+    }
+
+    fn high(&self) -> bool {
+        self.lfsr & 0b1 != 0
+    }
+}
+
+/// The envelope generator, shared by all three channels
+///
+/// Shape bits: ---- CAtH    Continue (C), attack (A, 1 = rising), alternate (t), hold (H)
+#[derive(Debug, Clone, Copy, Default)]
+struct Envelope {
+    period: u16,
+    counter: u16,
+    continue_: bool,
+    attack: bool,
+    alternate: bool,
+    hold: bool,
+    holding: bool,
+    step: u8,
+    level: u8,
+}
+
+impl Envelope {
+    fn write_shape(&mut self, val: u8) {
+        self.continue_ = val >> 3 & 0b1 != 0;
+        self.attack = val >> 2 & 0b1 != 0;
+        self.alternate = val >> 1 & 0b1 != 0;
+        self.hold = val & 0b1 != 0;
+        self.holding = false;
+        self.counter = 0;
+        self.step = 0;
+        self.update_level();
+    }
+
+    fn update_level(&mut self) {
+        self.level = if self.attack {
+            self.step
+        } else {
+            31 - self.step
+        };
+    }
+
+    /// Steps through 32 levels every `period` ticks
+    fn tick(&mut self) {
+        if self.holding {
+            return;
+        }
+
+        self.counter += 1;
+
+        if self.counter < self.period.max(1) {
+            return;
+        }
+
+        self.counter = 0;
+
+        if self.step < 31 {
+            self.step += 1;
+            self.update_level();
+            return;
+        }
+
+        // The end of a ramp
+        if !self.continue_ {
+            self.holding = true;
+            self.level = 0;
+        } else if self.hold {
+            self.holding = true;
+            self.level = if self.attack != self.alternate { 31 } else { 0 };
+        } else {
+            if self.alternate {
+                self.attack = !self.attack;
+            }
+
+            self.step = 0;
+            self.update_level();
+        }
+    }
+}
+
+/// The Sunsoft 5B, a YM2149F (AY-3-8910 family) inside an FME-7 mapper: three square wave
+/// tones, one noise generator and one envelope generator
+///
+/// $C000    ---- RRRR    Register select (R)
+/// $E000    Write to the selected register
+///
+/// $00-$05    Tone A, B and C periods: low 8 bits, then high 4 bits
+/// $06        ---P PPPP    Noise period (P)
+/// $07        --CB Acba    Noise disable for C, B, A; tone disable for c, b, a
+/// $08-$0A    ---E VVVV    Channel A, B and C: use the envelope (E), or fixed volume (V)
+/// $0B-$0C    Envelope period, low then high
+/// $0D        ---- CAtH    Envelope shape (restarts the envelope)
+///
+/// Volume is logarithmic, 3 dB per step of V (1.5 dB per envelope step). The chip's mix level
+/// is configurable: real carts are much louder than the default.
+#[derive(Debug, Clone)]
+pub struct Sunsoft5b {
+    register: u8,
+    tones: [Tone; 3],
+    noise: Noise,
+    envelope: Envelope,
+    tone_disabled: [bool; 3],
+    noise_disabled: [bool; 3],
+    volumes: [u8; 3],
+    use_envelope: [bool; 3],
+    cycle: u8,
+    mix_level: f64,
+}
+
+impl Default for Sunsoft5b {
+    fn default() -> Self {
+        Sunsoft5b::new(1.0)
+    }
+}
+
+impl Sunsoft5b {
+    /// `mix_level` is how loud a single channel at full volume is, in 2A03 pulses at volume 15
+    pub fn new(mix_level: f64) -> Self {
+        Sunsoft5b {
+            register: 0,
+            tones: [Tone::default(); 3],
+            noise: Noise {
+                period: 0,
+                counter: 0,
+                lfsr: 1,
+            },
+            envelope: Envelope::default(),
+            tone_disabled: [true; 3],
+            noise_disabled: [true; 3],
+            volumes: [0; 3],
+            use_envelope: [false; 3],
+            cycle: 0,
+            mix_level,
+        }
+    }
+
+    pub fn set_mix_level(&mut self, mix_level: f64) {
+        self.mix_level = mix_level;
+    }
+
+    fn write_register(&mut self, val: u8) {
+        match self.register {
+            reg @ (0x00 | 0x02 | 0x04) => {
+                let tone = &mut self.tones[usize::from(reg / 2)];
+                tone.period = tone.period & 0x0F00 | u16::from(val);
+            }
+            reg @ (0x01 | 0x03 | 0x05) => {
+                let tone = &mut self.tones[usize::from(reg / 2)];
+                tone.period = tone.period & 0x00FF | u16::from(val & 0b1111) << 8;
+            }
+            0x06 => self.noise.period = val & 0b1_1111,
+            0x07 => {
+                for channel in 0..3 {
+                    self.tone_disabled[channel] = val >> channel & 0b1 != 0;
+                    self.noise_disabled[channel] = val >> (channel + 3) & 0b1 != 0;
+                }
+            }
+            reg @ 0x08..=0x0A => {
+                let channel = usize::from(reg - 0x08);
+                self.use_envelope[channel] = val >> 4 & 0b1 != 0;
+                self.volumes[channel] = val & 0b1111;
+            }
+            0x0B => self.envelope.period = self.envelope.period & 0xFF00 | u16::from(val),
+            0x0C => self.envelope.period = self.envelope.period & 0x00FF | u16::from(val) << 8,
+            0x0D => self.envelope.write_shape(val),
+            _ => (),
+        }
+    }
+
+    /// A 5-bit level on the chip's logarithmic curve, from 0.0 (level 0 is silent) to 1.0
+    fn amplitude(level: u8) -> f64 {
+        if level == 0 {
+            0.0
+        } else {
+            10f64.powf((f64::from(level) - 31.0) * 1.5 / 20.0)
+        }
+    }
+
+    fn channel_output(&self, channel: usize) -> f64 {
+        let tone_high = self.tone_disabled[channel] || self.tones[channel].high;
+        let noise_high = self.noise_disabled[channel] || self.noise.high();
+
+        if !(tone_high && noise_high) {
+            return 0.0;
+        }
+
+        let level = if self.use_envelope[channel] {
+            self.envelope.level
+        } else if self.volumes[channel] == 0 {
+            0
+        } else {
+            self.volumes[channel] * 2 + 1
+        };
+
+        Sunsoft5b::amplitude(level)
+    }
+}
+
+impl ExpansionAudio for Sunsoft5b {
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xC000..=0xDFFF => self.register = val & 0b1111,
+            0xE000..=0xFFFF => self.write_register(val),
+            _ => (),
+        }
+    }
+
+    fn clock(&mut self) {
+        self.cycle += 1;
+
+        if self.cycle == CPU_CYCLES_PER_TICK / 2 {
+            self.envelope.tick();
+        } else if self.cycle == CPU_CYCLES_PER_TICK {
+            self.cycle = 0;
+            self.envelope.tick();
+            self.noise.tick();
+
+            for tone in &mut self.tones {
+                tone.tick();
+            }
+        }
+    }
+
+    fn output(&self) -> f64 {
+        let sum: f64 = (0..3).map(|channel| self.channel_output(channel)).sum();
+
+        sum * self.mix_level * OUTPUT_SCALE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_register(chip: &mut Sunsoft5b, reg: u8, val: u8) {
+        chip.write(0xC000, reg);
+        chip.write(0xE000, val);
+    }
+
+    /// The envelope's level right after `shape` is written and after each of the next 95 steps,
+    /// i.e. over three ramps
+    fn envelope_levels(shape: u8) -> Vec<u8> {
+        let mut envelope = Envelope {
+            period: 1,
+            ..Envelope::default()
+        };
+        envelope.write_shape(shape);
+
+        let mut levels = vec![envelope.level];
+
+        for _ in 1..96 {
+            envelope.tick();
+            levels.push(envelope.level);
+        }
+
+        levels
+    }
+
+    #[test]
+    fn envelope_shapes() {
+        let down: Vec<u8> = (0..32).rev().collect();
+        let up: Vec<u8> = (0..32).collect();
+        let low = [0; 32];
+        let high = [31; 32];
+        let (down, up, low, high) = (&down[..], &up[..], &low[..], &high[..]);
+
+        let shapes = [
+            (0b0000, [down, low, low]),
+            (0b0100, [up, low, low]),
+            (0b1000, [down, down, down]),
+            (0b1001, [down, low, low]),
+            (0b1010, [down, up, down]),
+            (0b1011, [down, high, high]),
+            (0b1100, [up, up, up]),
+            (0b1101, [up, high, high]),
+            (0b1110, [up, down, up]),
+            (0b1111, [up, low, low]),
+        ];
+
+        for (shape, ramps) in shapes {
+            assert_eq!(envelope_levels(shape), ramps.concat(), "shape {shape:04b}");
+        }
+    }
+
+    /// Channel A's output over `cycles` CPU cycles
+    fn outputs(chip: &mut Sunsoft5b, cycles: usize) -> Vec<f64> {
+        (0..cycles)
+            .map(|_| {
+                chip.clock();
+                chip.channel_output(0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tone_alternates_at_its_period() {
+        let mut chip = Sunsoft5b::default();
+        write_register(&mut chip, 0x00, 2);
+        write_register(&mut chip, 0x07, 0b11_1110);
+        write_register(&mut chip, 0x08, 0x0F);
+
+        // Flips every 2 ticks of 16 cycles
+        let outputs = outputs(&mut chip, 32 * 8);
+        let flips = outputs.windows(2).filter(|pair| pair[0] != pair[1]).count();
+
+        assert_eq!(flips, 8);
+        assert!(outputs.contains(&0.0));
+        assert!(outputs.contains(&Sunsoft5b::amplitude(31)));
+    }
+
+    #[test]
+    fn channel_with_tone_and_noise_disabled_holds_its_volume() {
+        let mut chip = Sunsoft5b::default();
+        write_register(&mut chip, 0x00, 2);
+        write_register(&mut chip, 0x07, 0b11_1111);
+        write_register(&mut chip, 0x08, 0x0F);
+
+        assert!(outputs(&mut chip, 1000)
+            .iter()
+            .all(|&output| output == Sunsoft5b::amplitude(31)));
+    }
+
+    #[test]
+    fn noise_gates_the_channel_randomly() {
+        let mut chip = Sunsoft5b::default();
+        write_register(&mut chip, 0x06, 1);
+        write_register(&mut chip, 0x07, 0b11_0111);
+        write_register(&mut chip, 0x08, 0x0F);
+
+        let outputs = outputs(&mut chip, 16 * 2 * 2000);
+        let high = outputs.iter().filter(|&&output| output > 0.0).count();
+
+        assert!(high > outputs.len() / 4 && high < outputs.len() * 3 / 4);
+    }
+
+    #[test]
+    fn tone_and_noise_together_are_anded() {
+        let mut chip = Sunsoft5b::default();
+        write_register(&mut chip, 0x00, 2);
+        write_register(&mut chip, 0x06, 1);
+        write_register(&mut chip, 0x07, 0b11_0110);
+        write_register(&mut chip, 0x08, 0x0F);
+
+        for _ in 0..16 * 2 * 100 {
+            chip.clock();
+
+            let expected = chip.tones[0].high && chip.noise.high();
+            assert_eq!(chip.channel_output(0) > 0.0, expected);
+        }
+    }
+
+    #[test]
+    fn volume_steps_are_3_db() {
+        let mut chip = Sunsoft5b::default();
+        write_register(&mut chip, 0x07, 0b11_1111);
+
+        write_register(&mut chip, 0x08, 0x00);
+        assert_eq!(chip.channel_output(0), 0.0);
+
+        write_register(&mut chip, 0x08, 0x0F);
+        let loud = chip.channel_output(0);
+        write_register(&mut chip, 0x08, 0x0E);
+        let quieter = chip.channel_output(0);
+
+        assert!((20.0 * (loud / quieter).log10() - 3.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,261 @@
+use super::ExpansionAudio;
+
+/// One VRC6 output step is about as loud as one step of a 2A03 pulse channel, i.e. a VRC6
+/// pulse at volume 15 matches a 2A03 pulse at volume 15 through the nonlinear mixer
+const OUTPUT_SCALE: f64 = 95.88 / (8128.0 / 15.0 + 100.0) / 15.0;
+
+/// $9003: ---- -ABH    Halt all channels (H), 16x frequency (B), 256x frequency (A)
+#[derive(Debug, Clone, Copy, Default)]
+struct FrequencyControl {
+    halt: bool,
+    shift: u8,
+}
+
+impl FrequencyControl {
+    fn write(&mut self, val: u8) {
+        self.halt = val & 0b1 != 0;
+        self.shift = if val & 0b100 != 0 {
+            8
+        } else if val & 0b10 != 0 {
+            4
+        } else {
+            0
+        };
+    }
+}
+
+/// A VRC6 pulse channel, with 16 steps and 8 duty settings
+///
+/// Byte 0: MDDD VVVV    Mode (M, 1 = ignore duty and output the volume constantly), duty (D), volume (V)
+/// Byte 1: PPPP PPPP    Period low (P)
+/// Byte 2: E--- PPPP    Enable (E), period high (P)
+#[derive(Debug, Clone, Default)]
+struct Pulse {
+    constant: bool,
+    duty: u8,
+    volume: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+}
+
+impl Pulse {
+    fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                self.constant = val >> 7 & 0b1 != 0;
+                self.duty = val >> 4 & 0b111;
+                self.volume = val & 0b1111;
+            }
+            1 => self.period = self.period & 0x0F00 | u16::from(val),
+            2 => {
+                self.period = self.period & 0x00FF | u16::from(val & 0b1111) << 8;
+                self.enabled = val >> 7 & 0b1 != 0;
+
+                // Disabling the channel resets its duty cycle
+                if !self.enabled {
+                    self.step = 0;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn clock(&mut self, control: FrequencyControl) {
+        if !self.enabled || control.halt {
+            return;
+        }
+
+        if self.timer == 0 {
+            self.timer = self.period >> control.shift;
+            self.step = (self.step + 1) % 16;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// 0 to 15
+    fn output(&self) -> u8 {
+        if self.enabled && (self.constant || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+/// The VRC6 sawtooth channel, which adds its rate to an accumulator on every other step, six
+/// times, and resets it on the fourteenth step
+///
+/// Byte 0: --AA AAAA    Accumulator rate (A)
+/// Byte 1: PPPP PPPP    Period low (P)
+/// Byte 2: E--- PPPP    Enable (E), period high (P)
+#[derive(Debug, Clone, Default)]
+struct Sawtooth {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Sawtooth {
+    fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => self.rate = val & 0b11_1111,
+            1 => self.period = self.period & 0x0F00 | u16::from(val),
+            2 => {
+                self.period = self.period & 0x00FF | u16::from(val & 0b1111) << 8;
+                self.enabled = val >> 7 & 0b1 != 0;
+
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn clock(&mut self, control: FrequencyControl) {
+        if !self.enabled || control.halt {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.period >> control.shift;
+        self.step += 1;
+
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step & 0b1 == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    /// 0 to 31: the top five bits of the accumulator
+    fn output(&self) -> u8 {
+        if self.enabled {
+            self.accumulator >> 3
+        } else {
+            0
+        }
+    }
+}
+
+/// Konami VRC6 expansion audio: two pulse channels and a sawtooth
+///
+/// $9000-$9002    Pulse 1
+/// $9003          Frequency control
+/// $A000-$A002    Pulse 2
+/// $B000-$B002    Sawtooth
+#[derive(Debug, Clone, Default)]
+pub struct Vrc6 {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    sawtooth: Sawtooth,
+    control: FrequencyControl,
+}
+
+impl ExpansionAudio for Vrc6 {
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x9000..=0x9002 => self.pulse1.write(addr - 0x9000, val),
+            0x9003 => self.control.write(val),
+            0xA000..=0xA002 => self.pulse2.write(addr - 0xA000, val),
+            0xB000..=0xB002 => self.sawtooth.write(addr - 0xB000, val),
+            _ => (),
+        }
+    }
+
+    fn clock(&mut self) {
+        self.pulse1.clock(self.control);
+        self.pulse2.clock(self.control);
+        self.sawtooth.clock(self.control);
+    }
+
+    fn output(&self) -> f64 {
+        let sum = self.pulse1.output() + self.pulse2.output() + self.sawtooth.output();
+        f64::from(sum) * OUTPUT_SCALE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clock `vrc6` `cycles` times, collecting `output` after each
+    fn outputs(vrc6: &mut Vrc6, cycles: usize, output: impl Fn(&Vrc6) -> u8) -> Vec<u8> {
+        (0..cycles)
+            .map(|_| {
+                vrc6.clock();
+                output(vrc6)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pulse_is_high_for_duty_plus_one_of_sixteen_steps() {
+        for duty in 0..8 {
+            let mut vrc6 = Vrc6::default();
+            vrc6.write(0x9000, duty << 4 | 0x0F);
+            vrc6.write(0x9001, 0);
+            vrc6.write(0x9002, 0x80);
+
+            let high = outputs(&mut vrc6, 16, |vrc6| vrc6.pulse1.output())
+                .into_iter()
+                .filter(|&output| output == 15)
+                .count();
+
+            assert_eq!(high, usize::from(duty) + 1, "duty {duty}");
+        }
+    }
+
+    #[test]
+    fn pulse_mode_bit_ignores_the_duty() {
+        let mut vrc6 = Vrc6::default();
+        vrc6.write(0xA000, 0x80 | 0x07);
+        vrc6.write(0xA002, 0x80);
+
+        assert_eq!(outputs(&mut vrc6, 16, |vrc6| vrc6.pulse2.output()), [7; 16]);
+        assert!(vrc6.output() > 0.0);
+    }
+
+    #[test]
+    fn sawtooth_adds_six_times_then_resets() {
+        let mut vrc6 = Vrc6::default();
+        vrc6.write(0xB000, 8);
+        vrc6.write(0xB001, 0);
+        vrc6.write(0xB002, 0x80);
+
+        let accumulator = |vrc6: &Vrc6| vrc6.sawtooth.accumulator;
+
+        assert_eq!(
+            outputs(&mut vrc6, 28, accumulator),
+            [
+                0, 8, 8, 16, 16, 24, 24, 32, 32, 40, 40, 48, 48, 0, //
+                0, 8, 8, 16, 16, 24, 24, 32, 32, 40, 40, 48, 48, 0,
+            ]
+        );
+    }
+
+    #[test]
+    fn halt_stops_every_channel() {
+        let mut vrc6 = Vrc6::default();
+        vrc6.write(0xB000, 8);
+        vrc6.write(0xB002, 0x80);
+        vrc6.write(0x9003, 0b1);
+
+        assert_eq!(
+            outputs(&mut vrc6, 4, |vrc6| vrc6.sawtooth.accumulator),
+            [0; 4]
+        );
+    }
+}
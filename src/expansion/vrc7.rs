@@ -0,0 +1,585 @@
+use std::f64::consts::PI;
+
+use super::ExpansionAudio;
+
+/// The VRC7 makes one sample every 72 cycles of its 3.58 MHz clock, i.e. every 36 CPU cycles
+const CPU_CYCLES_PER_SAMPLE: u8 = 36;
+
+/// A full-scale VRC7 channel is mixed about as loud as a 2A03 pulse at volume 15
+const OUTPUT_SCALE: f64 = 95.88 / (8128.0 / 15.0 + 100.0);
+
+/// Instruments 1-15, as dumped from the chip. Instrument 0 is the custom patch in $00-$07.
+const PATCH_ROM: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27], // Buzzy bell
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12], // Guitar
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12], // Wurly
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27], // Flute
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28], // Clarinet
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4], // Synth
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07], // Trumpet
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17], // Organ
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01], // Bells
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02], // Vibes
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12], // Vibraphone
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16], // Tutti
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02], // Fretless
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6], // Synth bass
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06], // Sweep
+];
+
+/// Twice the frequency multiplier, so that setting 0 (x0.5) stays an integer
+const MULTIPLIERS_X2: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+/// Key scale level attenuation in dB at block 7, indexed by the top four bits of the F-number.
+/// Each block below 7 takes 3 dB off.
+const KEY_SCALE_LEVELS: [f64; 16] = [
+    0.0, 9.0, 12.0, 13.875, 15.0, 16.125, 16.875, 17.625, 18.0, 18.75, 19.125, 19.5, 19.875, 20.25,
+    20.625, 21.0,
+];
+
+/// Envelope increments for the low two bits of the rate, over an 8-step cycle
+const ENVELOPE_STEPS: [[u8; 8]; 4] = [
+    [0, 1, 0, 1, 0, 1, 0, 1],
+    [0, 1, 0, 1, 1, 1, 0, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 1],
+];
+
+/// Envelope attenuation is 7 bits in 0.375 dB steps
+const ENVELOPE_MAX: u8 = 127;
+const ENVELOPE_STEP_DB: f64 = 0.375;
+
+/// Tremolo is a 3.7 Hz triangle up to 13 envelope steps (4.875 dB) deep
+const TREMOLO_STEPS: u32 = 210;
+const TREMOLO_SAMPLES_PER_STEP: u32 = 64;
+
+/// Vibrato is an 8-step cycle at 6.1 Hz
+const VIBRATO_SAMPLES_PER_STEP: u32 = 1024;
+
+/// One operator's half of an instrument
+#[derive(Debug, Clone, Copy, Default)]
+struct OperatorPatch {
+    tremolo: bool,
+    vibrato: bool,
+    /// Hold at the sustain level while the key is down, rather than decaying away
+    sustained: bool,
+    key_scale_rate: bool,
+    multiplier: u8,
+    key_scale_level: u8,
+    half_sine: bool,
+    attack_rate: u8,
+    decay_rate: u8,
+    sustain_level: u8,
+    release_rate: u8,
+}
+
+/// A two-operator instrument
+///
+/// $00: TVSK MMMM    Modulator tremolo (T), vibrato (V), sustained (S), key scale rate (K), multiplier (M)
+/// $01: TVSK MMMM    Carrier tremolo (T), vibrato (V), sustained (S), key scale rate (K), multiplier (M)
+/// $02: KKOO OOOO    Modulator key scale level (K), modulator output level (O)
+/// $03: KK-Q WFFF    Carrier key scale level (K), carrier half-sine (Q), modulator half-sine (W), feedback (F)
+/// $04: AAAA DDDD    Modulator attack (A), decay (D)
+/// $05: AAAA DDDD    Carrier attack (A), decay (D)
+/// $06: SSSS RRRR    Modulator sustain level (S), release (R)
+/// $07: SSSS RRRR    Carrier sustain level (S), release (R)
+#[derive(Debug, Clone, Copy, Default)]
+struct Patch {
+    modulator: OperatorPatch,
+    carrier: OperatorPatch,
+    modulator_level: u8,
+    feedback: u8,
+}
+
+impl Patch {
+    fn from_bytes(bytes: &[u8; 8]) -> Self {
+        let operator = |op: usize| OperatorPatch {
+            tremolo: bytes[op] >> 7 & 0b1 != 0,
+            vibrato: bytes[op] >> 6 & 0b1 != 0,
+            sustained: bytes[op] >> 5 & 0b1 != 0,
+            key_scale_rate: bytes[op] >> 4 & 0b1 != 0,
+            multiplier: bytes[op] & 0b1111,
+            key_scale_level: bytes[2 + op] >> 6,
+            half_sine: bytes[3] >> (3 + op) & 0b1 != 0,
+            attack_rate: bytes[4 + op] >> 4,
+            decay_rate: bytes[4 + op] & 0b1111,
+            sustain_level: bytes[6 + op] >> 4,
+            release_rate: bytes[6 + op] & 0b1111,
+        };
+
+        Patch {
+            modulator: operator(0),
+            carrier: operator(1),
+            modulator_level: bytes[2] & 0b11_1111,
+            feedback: bytes[3] & 0b111,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EnvelopeState {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+/// The global counters that drive the envelopes, tremolo and vibrato
+#[derive(Debug, Clone, Copy, Default)]
+struct Lfo {
+    envelope_counter: u32,
+    tremolo_counter: u32,
+    vibrato_counter: u32,
+}
+
+impl Lfo {
+    fn clock(&mut self) {
+        self.envelope_counter = self.envelope_counter.wrapping_add(1);
+        self.tremolo_counter =
+            (self.tremolo_counter + 1) % (TREMOLO_STEPS * TREMOLO_SAMPLES_PER_STEP);
+        self.vibrato_counter = (self.vibrato_counter + 1) % (8 * VIBRATO_SAMPLES_PER_STEP);
+    }
+
+    /// 0 to 13 envelope steps
+    fn tremolo(&self) -> u8 {
+        let step = self.tremolo_counter / TREMOLO_SAMPLES_PER_STEP;
+        let triangle = if step < TREMOLO_STEPS / 2 {
+            step
+        } else {
+            TREMOLO_STEPS - 1 - step
+        };
+
+        (triangle / 8) as u8
+    }
+
+    /// How far to bend an F-number up or down, which scales with the F-number's top 3 bits
+    fn vibrato(&self, fnum: u16) -> i32 {
+        let depth = i32::from(fnum >> 6);
+        let half = depth >> 1;
+
+        match self.vibrato_counter / VIBRATO_SAMPLES_PER_STEP {
+            1 | 3 => half,
+            2 => depth,
+            5 | 7 => -half,
+            6 => -depth,
+            _ => 0,
+        }
+    }
+
+    /// The envelope increment for an effective rate of 0-63 on this sample
+    fn envelope_increment(&self, rate: u8) -> u8 {
+        if rate == 0 {
+            return 0;
+        }
+
+        let rate_high = u32::from(rate >> 2);
+        let steps = &ENVELOPE_STEPS[(rate & 0b11) as usize];
+
+        if rate_high < 13 {
+            let shift = 13 - rate_high;
+
+            if self.envelope_counter & ((1 << shift) - 1) != 0 {
+                0
+            } else {
+                steps[(self.envelope_counter >> shift & 0b111) as usize]
+            }
+        } else {
+            steps[(self.envelope_counter & 0b111) as usize] << (rate_high - 13)
+        }
+    }
+}
+
+/// One sine-wave operator with its own phase and envelope
+#[derive(Debug, Clone)]
+struct Operator {
+    /// 19 bits per cycle
+    phase: u32,
+    state: EnvelopeState,
+    attenuation: u8,
+    /// The last two outputs, for the modulator's self-feedback
+    outputs: [f64; 2],
+}
+
+impl Default for Operator {
+    fn default() -> Self {
+        Operator {
+            phase: 0,
+            state: EnvelopeState::Release,
+            attenuation: ENVELOPE_MAX,
+            outputs: [0.0; 2],
+        }
+    }
+}
+
+impl Operator {
+    fn key_on(&mut self) {
+        self.phase = 0;
+        self.state = EnvelopeState::Attack;
+    }
+
+    fn key_off(&mut self) {
+        self.state = EnvelopeState::Release;
+    }
+
+    /// `key_scale` is the block and F-number MSB, which speed up the envelope on high notes
+    fn clock_envelope(&mut self, patch: &OperatorPatch, sustain: bool, key_scale: u8, lfo: &Lfo) {
+        let base_rate = match self.state {
+            EnvelopeState::Attack => patch.attack_rate,
+            EnvelopeState::Decay => patch.decay_rate,
+            EnvelopeState::Sustain if patch.sustained => 0,
+            EnvelopeState::Sustain => patch.release_rate,
+            EnvelopeState::Release if sustain => 5,
+            EnvelopeState::Release if patch.sustained => patch.release_rate,
+            EnvelopeState::Release => 7,
+        };
+
+        let rate = if base_rate == 0 {
+            0
+        } else {
+            let key_scale = if patch.key_scale_rate {
+                key_scale
+            } else {
+                key_scale >> 2
+            };
+
+            (base_rate * 4 + key_scale).min(63)
+        };
+
+        let increment = lfo.envelope_increment(rate);
+
+        match self.state {
+            EnvelopeState::Attack => {
+                if rate >= 60 {
+                    self.attenuation = 0;
+                } else if increment > 0 {
+                    let step = (u16::from(self.attenuation) + 1) * u16::from(increment) / 8;
+                    self.attenuation = self.attenuation.saturating_sub(step.max(1) as u8);
+                }
+
+                if self.attenuation == 0 {
+                    self.state = EnvelopeState::Decay;
+                }
+            }
+            _ => {
+                self.attenuation = (self.attenuation + increment).min(ENVELOPE_MAX);
+
+                if self.state == EnvelopeState::Decay && self.attenuation >= patch.sustain_level * 8
+                {
+                    self.state = EnvelopeState::Sustain;
+                }
+            }
+        }
+    }
+
+    /// `modulation` is in cycles; the output is -1.0 to 1.0
+    fn output(&mut self, patch: &OperatorPatch, modulation: f64, attenuation_db: f64) -> f64 {
+        // A finished envelope is silent rather than just very quiet
+        if self.attenuation == ENVELOPE_MAX {
+            self.outputs = [self.outputs[1], 0.0];
+            return 0.0;
+        }
+
+        let phase = f64::from(self.phase) / f64::from(1 << 19) + modulation;
+        let wave = (2.0 * PI * phase).sin();
+
+        let wave = if patch.half_sine && wave < 0.0 {
+            0.0
+        } else {
+            wave
+        };
+
+        let output = wave * 10f64.powf(-attenuation_db / 20.0);
+
+        self.outputs = [self.outputs[1], output];
+
+        output
+    }
+}
+
+/// One of the six FM channels
+///
+/// $10-$15: LLLL LLLL    F-number low (L)
+/// $20-$25: --ST OOOH    Sustain (S), key on (T), octave/block (O), F-number high (H)
+/// $30-$35: IIII VVVV    Instrument (I), volume (V, in 3 dB steps of attenuation)
+#[derive(Debug, Clone, Default)]
+struct Channel {
+    fnum: u16,
+    block: u8,
+    key_on: bool,
+    sustain: bool,
+    instrument: u8,
+    volume: u8,
+    modulator: Operator,
+    carrier: Operator,
+}
+
+impl Channel {
+    fn write_frequency_high(&mut self, val: u8) {
+        self.fnum = self.fnum & 0x00FF | u16::from(val & 0b1) << 8;
+        self.block = val >> 1 & 0b111;
+        self.sustain = val >> 5 & 0b1 != 0;
+
+        let key_on = val >> 4 & 0b1 != 0;
+
+        if key_on && !self.key_on {
+            self.modulator.key_on();
+            self.carrier.key_on();
+        } else if !key_on && self.key_on {
+            self.modulator.key_off();
+            self.carrier.key_off();
+        }
+
+        self.key_on = key_on;
+    }
+
+    fn key_scale(&self) -> u8 {
+        self.block << 1 | (self.fnum >> 8) as u8
+    }
+
+    fn key_scale_level_db(&self, level: u8) -> f64 {
+        if level == 0 {
+            return 0.0;
+        }
+
+        let db = KEY_SCALE_LEVELS[(self.fnum >> 5) as usize] - 3.0 * f64::from(7 - self.block);
+
+        db.max(0.0) / f64::from(1 << (3 - level))
+    }
+
+    fn phase_increment(&self, patch: &OperatorPatch, lfo: &Lfo) -> u32 {
+        let fnum = if patch.vibrato {
+            (i32::from(self.fnum) + lfo.vibrato(self.fnum)).max(0) as u32
+        } else {
+            u32::from(self.fnum)
+        };
+
+        (fnum << self.block) * MULTIPLIERS_X2[patch.multiplier as usize] / 2
+    }
+
+    fn attenuation_db(
+        &self,
+        operator: &Operator,
+        patch: &OperatorPatch,
+        level_db: f64,
+        lfo: &Lfo,
+    ) -> f64 {
+        let tremolo = if patch.tremolo { lfo.tremolo() } else { 0 };
+
+        f64::from(operator.attenuation.saturating_add(tremolo)) * ENVELOPE_STEP_DB
+            + level_db
+            + self.key_scale_level_db(patch.key_scale_level)
+    }
+
+    fn sample(&mut self, patch: &Patch, lfo: &Lfo) -> f64 {
+        let key_scale = self.key_scale();
+
+        self.modulator
+            .clock_envelope(&patch.modulator, self.sustain, key_scale, lfo);
+        self.carrier
+            .clock_envelope(&patch.carrier, self.sustain, key_scale, lfo);
+
+        self.modulator.phase =
+            (self.modulator.phase + self.phase_increment(&patch.modulator, lfo)) & ((1 << 19) - 1);
+        self.carrier.phase =
+            (self.carrier.phase + self.phase_increment(&patch.carrier, lfo)) & ((1 << 19) - 1);
+
+        // Feedback of 1 bends the phase by up to pi/16, and each step doubles it
+        let feedback = if patch.feedback == 0 {
+            0.0
+        } else {
+            let [previous, last] = self.modulator.outputs;
+            (previous + last) / 2.0 * f64::from(1 << (patch.feedback - 1)) / 32.0
+        };
+
+        let modulator_db = self.attenuation_db(
+            &self.modulator,
+            &patch.modulator,
+            f64::from(patch.modulator_level) * 0.75,
+            lfo,
+        );
+        let modulator = self
+            .modulator
+            .output(&patch.modulator, feedback, modulator_db);
+
+        // A full-scale modulator bends the carrier's phase by up to two cycles (4π) either way,
+        // as on the OPLL
+        let carrier_db = self.attenuation_db(
+            &self.carrier,
+            &patch.carrier,
+            f64::from(self.volume) * 3.0,
+            lfo,
+        );
+
+        self.carrier
+            .output(&patch.carrier, modulator * 2.0, carrier_db)
+    }
+}
+
+/// Konami VRC7 expansion audio: a cut-down Yamaha YM2413 (OPLL) with six two-operator FM
+/// channels, 15 built-in instruments and one custom instrument
+///
+/// $9010    Register select
+/// $9030    Register write
+/// $E000    Bit 6 silences and resets the sound chip
+#[derive(Debug, Clone, Default)]
+pub struct Vrc7 {
+    address: u8,
+    custom_patch: [u8; 8],
+    channels: [Channel; 6],
+    lfo: Lfo,
+    silenced: bool,
+    cycle: u8,
+    output: f64,
+}
+
+impl Vrc7 {
+    fn write_register(&mut self, reg: u8, val: u8) {
+        match reg {
+            0x00..=0x07 => self.custom_patch[reg as usize] = val,
+            0x10..=0x15 => {
+                let channel = &mut self.channels[(reg - 0x10) as usize];
+                channel.fnum = channel.fnum & 0x0100 | u16::from(val);
+            }
+            0x20..=0x25 => self.channels[(reg - 0x20) as usize].write_frequency_high(val),
+            0x30..=0x35 => {
+                let channel = &mut self.channels[(reg - 0x30) as usize];
+                channel.instrument = val >> 4;
+                channel.volume = val & 0b1111;
+            }
+            _ => (),
+        }
+    }
+
+    fn patch(&self, instrument: u8) -> Patch {
+        match instrument {
+            0 => Patch::from_bytes(&self.custom_patch),
+            _ => Patch::from_bytes(&PATCH_ROM[instrument as usize - 1]),
+        }
+    }
+
+    fn sample(&mut self) -> f64 {
+        self.lfo.clock();
+
+        let mut sum = 0.0;
+
+        for i in 0..self.channels.len() {
+            let patch = self.patch(self.channels[i].instrument);
+            sum += self.channels[i].sample(&patch, &self.lfo);
+        }
+
+        sum
+    }
+}
+
+impl ExpansionAudio for Vrc7 {
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x9010 => self.address = val,
+            0x9030 => self.write_register(self.address, val),
+            0xE000 => {
+                self.silenced = val >> 6 & 0b1 != 0;
+
+                if self.silenced {
+                    *self = Vrc7 {
+                        silenced: true,
+                        ..Default::default()
+                    };
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.silenced {
+            return;
+        }
+
+        self.cycle += 1;
+
+        if self.cycle == CPU_CYCLES_PER_SAMPLE {
+            self.cycle = 0;
+            self.output = self.sample();
+        }
+    }
+
+    fn output(&self) -> f64 {
+        self.output * OUTPUT_SCALE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_register(vrc7: &mut Vrc7, reg: u8, val: u8) {
+        vrc7.write(0x9010, reg);
+        vrc7.write(0x9030, val);
+    }
+
+    /// The loudest output over `cycles` CPU cycles
+    fn peak(vrc7: &mut Vrc7, cycles: usize) -> f64 {
+        (0..cycles)
+            .map(|_| {
+                vrc7.clock();
+                vrc7.output().abs()
+            })
+            .fold(0.0, f64::max)
+    }
+
+    /// Key on channel 0 with the flute at full volume, around 440 Hz
+    fn key_on(vrc7: &mut Vrc7) {
+        write_register(vrc7, 0x30, 0x40);
+        write_register(vrc7, 0x10, 0x20);
+        write_register(vrc7, 0x20, 0b0001_1001);
+    }
+
+    #[test]
+    fn silent_until_a_key_is_on() {
+        let mut vrc7 = Vrc7::default();
+        write_register(&mut vrc7, 0x30, 0x40);
+        write_register(&mut vrc7, 0x10, 0x20);
+
+        assert_eq!(peak(&mut vrc7, 36 * 100), 0.0);
+    }
+
+    #[test]
+    fn key_on_attacks_to_an_audible_note() {
+        let mut vrc7 = Vrc7::default();
+        key_on(&mut vrc7);
+
+        // A tenth of a second
+        assert!(peak(&mut vrc7, 178_977) > 0.1 * OUTPUT_SCALE);
+
+        assert!(vrc7.channels[0].carrier.attenuation < ENVELOPE_MAX);
+
+        // The flute's carrier attack is slow, but it's at full level within a second
+        peak(&mut vrc7, 1_789_773);
+        assert_ne!(vrc7.channels[0].carrier.state, EnvelopeState::Attack);
+    }
+
+    #[test]
+    fn key_off_releases_to_silence() {
+        let mut vrc7 = Vrc7::default();
+        key_on(&mut vrc7);
+        peak(&mut vrc7, 178_977);
+
+        write_register(&mut vrc7, 0x20, 0b0000_1001);
+
+        // Two seconds is plenty for the flute's release
+        peak(&mut vrc7, 2 * 1_789_773);
+        assert_eq!(vrc7.channels[0].carrier.attenuation, ENVELOPE_MAX);
+        assert_eq!(peak(&mut vrc7, 36 * 100), 0.0);
+    }
+
+    #[test]
+    fn e000_bit_6_silences_the_chip() {
+        let mut vrc7 = Vrc7::default();
+        key_on(&mut vrc7);
+        peak(&mut vrc7, 178_977);
+
+        vrc7.write(0xE000, 0x40);
+
+        assert_eq!(peak(&mut vrc7, 36 * 100), 0.0);
+    }
+}
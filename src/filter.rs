@@ -0,0 +1,207 @@
+use std::f64::consts::PI;
+
+use dasp::signal::Signal;
+
+/// The analog output stage a render should imitate.
+///
+/// NES front-loader: 90 Hz high-pass, 440 Hz high-pass, 14 kHz low-pass
+/// Famicom:          37 Hz high-pass (the RF stage that follows it varies too much to model)
+/// None:             the raw mixer output, DC offset and all
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FilterPreset {
+    #[default]
+    NesFrontLoader,
+    Famicom,
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FilterKind {
+    HighPass,
+    LowPass,
+}
+
+impl FilterPreset {
+    fn stages(self) -> &'static [(FilterKind, f64)] {
+        match self {
+            FilterPreset::NesFrontLoader => &[
+                (FilterKind::HighPass, 90.0),
+                (FilterKind::HighPass, 440.0),
+                (FilterKind::LowPass, 14_000.0),
+            ],
+            FilterPreset::Famicom => &[(FilterKind::HighPass, 37.0)],
+            FilterPreset::None => &[],
+        }
+    }
+}
+
+/// A single first-order (RC) filter stage
+#[derive(Debug, Clone)]
+pub struct OnePole {
+    kind: FilterKind,
+    alpha: f64,
+    prev_input: f64,
+    prev_output: f64,
+}
+
+impl OnePole {
+    pub fn high_pass(sample_rate: f64, cutoff_hz: f64) -> Self {
+        Self::new(FilterKind::HighPass, sample_rate, cutoff_hz)
+    }
+
+    pub fn low_pass(sample_rate: f64, cutoff_hz: f64) -> Self {
+        Self::new(FilterKind::LowPass, sample_rate, cutoff_hz)
+    }
+
+    fn new(kind: FilterKind, sample_rate: f64, cutoff_hz: f64) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff_hz);
+        let dt = 1.0 / sample_rate;
+
+        let alpha = match kind {
+            FilterKind::HighPass => rc / (rc + dt),
+            FilterKind::LowPass => dt / (rc + dt),
+        };
+
+        OnePole {
+            kind,
+            alpha,
+            prev_input: 0.0,
+            prev_output: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, input: f64) -> f64 {
+        let output = match self.kind {
+            FilterKind::HighPass => self.alpha * (self.prev_output + input - self.prev_input),
+            FilterKind::LowPass => self.prev_output + self.alpha * (input - self.prev_output),
+        };
+
+        self.prev_input = input;
+        self.prev_output = output;
+
+        output
+    }
+}
+
+/// The stages of a `FilterPreset`, applied in order
+#[derive(Debug, Clone)]
+pub struct FilterChain {
+    stages: Vec<OnePole>,
+}
+
+impl FilterChain {
+    pub fn new(preset: FilterPreset, sample_rate: f64) -> Self {
+        let stages = preset
+            .stages()
+            .iter()
+            .map(|&(kind, cutoff_hz)| OnePole::new(kind, sample_rate, cutoff_hz))
+            .collect();
+
+        FilterChain { stages }
+    }
+
+    pub fn process(&mut self, input: f64) -> f64 {
+        self.stages
+            .iter_mut()
+            .fold(input, |sample, stage| stage.process(sample))
+    }
+}
+
+/// A `Signal` adapter that runs every frame of `signal` through a `FilterChain`
+#[derive(Debug, Clone)]
+pub struct Filtered<S> {
+    signal: S,
+    chain: FilterChain,
+}
+
+impl<S: Signal<Frame = f64>> Filtered<S> {
+    pub fn new(signal: S, preset: FilterPreset, sample_rate: f64) -> Self {
+        Filtered {
+            signal,
+            chain: FilterChain::new(preset, sample_rate),
+        }
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for Filtered<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> f64 {
+        let input = self.signal.next();
+        self.chain.process(input)
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.signal.is_exhausted()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dasp::signal;
+
+    const SAMPLE_RATE: f64 = 44_100.0;
+
+    /// The peak output of `filter` over the second half of a second of a unit sine at `hz`, once
+    /// it's settled
+    fn sine_peak(mut filter: impl FnMut(f64) -> f64, hz: f64) -> f64 {
+        (0..SAMPLE_RATE as usize)
+            .map(|n| filter((2.0 * PI * hz * n as f64 / SAMPLE_RATE).sin()))
+            .skip(SAMPLE_RATE as usize / 2)
+            .fold(0.0, |peak, sample: f64| peak.max(sample.abs()))
+    }
+
+    #[test]
+    fn high_pass_rejects_dc() {
+        let mut high_pass = OnePole::high_pass(SAMPLE_RATE, 90.0);
+        let settled = (0..SAMPLE_RATE as usize)
+            .map(|_| high_pass.process(1.0))
+            .last()
+            .unwrap();
+
+        assert!(settled.abs() < 1e-3, "{settled}");
+
+        for preset in [FilterPreset::NesFrontLoader, FilterPreset::Famicom] {
+            let mut chain = FilterChain::new(preset, SAMPLE_RATE);
+            let settled = (0..SAMPLE_RATE as usize)
+                .map(|_| chain.process(0.5))
+                .last()
+                .unwrap();
+
+            assert!(settled.abs() < 1e-3, "{preset:?}: {settled}");
+        }
+
+        let settled = Filtered::new(
+            signal::gen(|| 0.5),
+            FilterPreset::NesFrontLoader,
+            SAMPLE_RATE,
+        )
+        .take(SAMPLE_RATE as usize)
+        .last()
+        .unwrap();
+
+        assert!(settled.abs() < 1e-3, "{settled}");
+    }
+
+    #[test]
+    fn no_filter_passes_everything() {
+        let mut chain = FilterChain::new(FilterPreset::None, SAMPLE_RATE);
+
+        assert_eq!(chain.process(0.5), 0.5);
+        assert_eq!(chain.process(-1.0), -1.0);
+    }
+
+    #[test]
+    fn low_pass_attenuates_above_the_cutoff() {
+        let gain = |hz| {
+            let mut low_pass = OnePole::low_pass(SAMPLE_RATE, 1000.0);
+            sine_peak(|sample| low_pass.process(sample), hz)
+        };
+
+        assert!(gain(100.0) > 0.98, "{}", gain(100.0));
+        // -3 dB at the cutoff
+        assert!((0.65..0.75).contains(&gain(1000.0)), "{}", gain(1000.0));
+        assert!(gain(10_000.0) < 0.15, "{}", gain(10_000.0));
+    }
+}
@@ -1,2 +1,10 @@
+pub mod apu;
+pub mod cpu;
+pub mod driver;
+pub mod expansion;
+pub mod filter;
 pub mod nsf;
+pub mod region;
+pub mod render;
+pub mod tuning;
 pub mod wav;
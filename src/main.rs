@@ -1,77 +1,47 @@
-use nsf::NsfHeader;
-use tetanes::{
-    audio::Audio,
-    common::NesRegion,
-    control_deck::ControlDeck,
-    memory::{MemWrite, RamState},
-};
+use std::{env, path::Path, time::Duration};
 
-use pix_engine::prelude::*;
+use anyhow::{anyhow, Context};
+use tetanes::audio::Audio;
 
-use crate::nsf::load_nsf_as_cart_data;
+use pix_engine::prelude::*;
 
-use std::iter::zip;
+use wav_creator::driver::NsfDriver;
+use wav_creator::expansion::{namco163::Mixing, ExpansionOptions};
+use wav_creator::nsf::load_nsf;
+use wav_creator::render::{
+    render_album, track_duration, write_track_wav, DEFAULT_FILE_NAME_TEMPLATE,
+};
 
-mod nsf;
+const SAMPLE_RATE: f64 = 44_100.0;
 
+/// Plays an NSF in real time through the audio device
 struct NesMusicPlayer {
-    header: NsfHeader,
-    control_deck: ControlDeck,
+    driver: NsfDriver,
+    cycles_remaining: f64,
     audio: Audio,
 }
 
 impl NesMusicPlayer {
-    pub fn from_nsf(nsf_file_name: &str) -> anyhow::Result<Self> {
-        let (header, rom) = load_nsf_as_cart_data(nsf_file_name)?;
-        println!("{:X?}", header);
-
-        let mut control_deck = ControlDeck::new(NesRegion::Ntsc, RamState::AllZeros);
-        control_deck.cpu_mut().debugging = true;
-
-        control_deck.load_rom(
-            header.song_name.to_str().expect("bad song name"),
-            &mut &rom[..],
-        )?;
-
-        for addr in 0..=0x07FF {
-            control_deck.cpu_mut().write(addr, 0x0);
-        }
-
-        for addr in 0x6000..=0x7FFF {
-            control_deck.cpu_mut().write(addr, 0x0);
+    /// A player for `track` of the NSF, or its starting song
+    pub fn from_nsf(
+        nsf_file_name: &str,
+        track: Option<u8>,
+        options: &ExpansionOptions,
+    ) -> anyhow::Result<Self> {
+        let (header, data) = load_nsf(nsf_file_name)?;
+
+        let track = track.unwrap_or(header.starting_song);
+        let driver = NsfDriver::new(header, &data, track, SAMPLE_RATE, options)?;
+
+        for warning in driver.warnings() {
+            println!("{warning}");
         }
 
-        for addr in 0x4000..=0x4013 {
-            control_deck.cpu_mut().write(addr, 0x0);
-        }
-
-        control_deck.cpu_mut().write(0x4015, 0x0);
-        control_deck.cpu_mut().write(0x4015, 0x0F);
-        control_deck.cpu_mut().write(0x4017, 0x40);
-
-        for (addr, val) in zip(0x5FF8..=0x5FFF, header.bankswitch_init) {
-            control_deck.cpu_mut().write(addr, val);
-        }
-
-        let sp = control_deck.cpu().sp;
-        let play_addr_bytes = (header.play_address - 1).to_le_bytes();
-
-        control_deck
-            .cpu_mut()
-            .write(0x0100 + u16::from(sp) + 1, play_addr_bytes[0]);
-
-        control_deck
-            .cpu_mut()
-            .write(0x0100 + u16::from(sp) + 2, play_addr_bytes[1]);
-
-        control_deck.cpu_mut().acc = header.starting_song;
-        control_deck.cpu_mut().pc = header.init_address;
-
-        let audio = Audio::new(control_deck.apu().sample_rate(), 44_100.0, 4096);
+        let audio = Audio::new(SAMPLE_RATE as f32, 44_100.0, 4096);
 
         Ok(NesMusicPlayer {
-            header,
-            control_deck,
+            driver,
+            cycles_remaining: 0.0,
             audio,
         })
     }
@@ -84,21 +54,70 @@ impl AppState for NesMusicPlayer {
     }
 
     fn on_update(&mut self, s: &mut PixState) -> PixResult<()> {
-        let seconds_to_run = s.delta_time().as_secs_f32().clamp(0.0, 1.0 / 60.0);
-        self.control_deck.clock_seconds(seconds_to_run)?;
-        let samples = self.control_deck.audio_samples();
-        self.audio.output(samples, false, 0.0005);
-        self.control_deck.clear_audio_samples();
-        self.control_deck.cpu_mut().pc = self.header.play_address;
+        let seconds_to_run = s.delta_time().as_secs_f64().clamp(0.0, 1.0 / 60.0);
+        self.cycles_remaining += self.driver.clock_rate() * seconds_to_run;
+
+        while self.cycles_remaining > 0.0 {
+            self.cycles_remaining -= self.driver.step()? as f64;
+        }
+
+        let samples: Vec<f32> = self
+            .driver
+            .take_samples()
+            .into_iter()
+            .map(|sample| sample as f32)
+            .collect();
+        self.audio.output(&samples, false, 0.0005);
 
         Ok(())
     }
 }
 
-fn main() -> anyhow::Result<()> {
-    env_logger::init();
+const USAGE: &str = "usage:
+    wav-creator play [options] <nsf> [track]
+    wav-creator render [options] <nsf> <wav> [track] [seconds]
+    wav-creator render-all [options] <nsf> <dir> [file name template]
+
+options:
+    --5b-mix=<level>    How loud a Sunsoft 5B channel is, against a 2A03 pulse (default 1.0)
+    --n163-mixing=<multiplexed|smooth>
+                        Whether Namco 163 channels take turns like on the real chip
+                        (multiplexed, the default) or are averaged
+
+Tracks count from 1, and default to the NSF's starting song. Renders last as long as the NSF's
+metadata says, or 150 seconds if it doesn't. File name templates must have {track} in them, can
+use {title}, and default to \"{track} - {title}.wav\".";
+
+fn parse_track(track: &str) -> anyhow::Result<u8> {
+    track
+        .parse()
+        .with_context(|| format!("{track} isn't a track number"))
+}
+
+/// Expansion chip settings from `--name=value` flags
+fn parse_options(flags: &[&str]) -> anyhow::Result<ExpansionOptions> {
+    let mut options = ExpansionOptions::default();
+
+    for flag in flags {
+        match flag.split_once('=') {
+            Some(("--5b-mix", level)) => {
+                options.sunsoft5b_mix_level = level
+                    .parse()
+                    .ok()
+                    .filter(|level: &f64| level.is_finite() && *level >= 0.0)
+                    .ok_or_else(|| anyhow!("{level} isn't a mix level\n\n{USAGE}"))?;
+            }
+            Some(("--n163-mixing", "multiplexed")) => options.namco163_mixing = Mixing::Multiplexed,
+            Some(("--n163-mixing", "smooth")) => options.namco163_mixing = Mixing::Smooth,
+            _ => return Err(anyhow!("unknown option {flag}\n\n{USAGE}")),
+        }
+    }
+
+    Ok(options)
+}
 
-    let mut music_player = NesMusicPlayer::from_nsf("mario.nsf")?;
+fn play(nsf_file_name: &str, track: Option<u8>, options: &ExpansionOptions) -> anyhow::Result<()> {
+    let mut music_player = NesMusicPlayer::from_nsf(nsf_file_name, track, options)?;
 
     let mut engine = PixEngine::builder()
         .hidden()
@@ -110,3 +129,83 @@ fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Render one track of an NSF to a WAV, as fast as the emulator runs, for `seconds` or however
+/// long its metadata says it is
+fn render(
+    nsf_file_name: &str,
+    wav_file_name: &str,
+    track: Option<u8>,
+    seconds: Option<&str>,
+    options: &ExpansionOptions,
+) -> anyhow::Result<()> {
+    let (header, data) = load_nsf(nsf_file_name)?;
+    let track = track.unwrap_or(header.starting_song);
+
+    let duration = match seconds {
+        Some(seconds) => {
+            let duration = seconds
+                .parse()
+                .ok()
+                .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                .ok_or_else(|| anyhow!("{seconds} isn't a number of seconds\n\n{USAGE}"))?;
+
+            (duration, Duration::ZERO)
+        }
+        None => track_duration(&header, track),
+    };
+
+    write_track_wav(
+        &header,
+        &data,
+        track,
+        duration,
+        options,
+        Path::new(wav_file_name),
+    )
+    .with_context(|| format!("rendering {nsf_file_name} failed"))
+}
+
+/// Render every track of an NSF into `dir`
+fn render_all(
+    nsf_file_name: &str,
+    dir: &str,
+    template: &str,
+    options: &ExpansionOptions,
+) -> anyhow::Result<()> {
+    let (header, data) = load_nsf(nsf_file_name)?;
+
+    let paths = render_album(&header, &data, Path::new(dir), template, options)
+        .with_context(|| format!("rendering {nsf_file_name} failed"))?;
+
+    for path in paths {
+        println!("{}", path.display());
+    }
+
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (flags, args): (Vec<&str>, Vec<&str>) = args
+        .iter()
+        .map(String::as_str)
+        .partition(|arg| arg.starts_with("--"));
+
+    let options = &parse_options(&flags)?;
+
+    match args[..] {
+        ["play", nsf] => play(nsf, None, options),
+        ["play", nsf, track] => play(nsf, Some(parse_track(track)?), options),
+        ["render", nsf, wav] => render(nsf, wav, None, None, options),
+        ["render", nsf, wav, track] => render(nsf, wav, Some(parse_track(track)?), None, options),
+        ["render", nsf, wav, track, seconds] => {
+            render(nsf, wav, Some(parse_track(track)?), Some(seconds), options)
+        }
+        ["render-all", nsf, dir] => render_all(nsf, dir, DEFAULT_FILE_NAME_TEMPLATE, options),
+        ["render-all", nsf, dir, template] => render_all(nsf, dir, template, options),
+        _ => Err(anyhow!(USAGE)),
+    }
+}
@@ -1,10 +1,38 @@
-use anyhow::Result;
 use nom::{
     bytes::complete::{tag, take},
-    number::complete::{le_u16, le_u8},
+    number::complete::{le_u16, le_u32, le_u8},
     IResult,
 };
-use std::{ffi::CString, fs::read, path::Path};
+use std::{fs, ops::RangeInclusive, path::Path, time::Duration};
+
+use crate::expansion::ExpansionChips;
+use crate::region::Region;
+
+use self::memory::NsfMemory;
+
+pub use self::edit::EditError;
+pub use self::error::NsfError;
+pub use self::lint::{lint, Lint, Severity};
+pub use self::nsfe::TrackInfo;
+pub use self::text::{NsfText, TextEncoding};
+pub use self::write::{write_nsf, write_nsf2, write_nsfe};
+
+mod edit;
+mod error;
+pub mod irq;
+mod lint;
+pub mod memory;
+mod nsfe;
+mod text;
+mod write;
+
+pub type Result<T> = std::result::Result<T, NsfError>;
+
+/// The NSF2 `flags` bits
+pub const IRQ_FLAG: u8 = 0b0001_0000;
+pub const NON_RETURNING_INIT_FLAG: u8 = 0b0010_0000;
+pub const NO_PLAY_FLAG: u8 = 0b0100_0000;
+pub const METADATA_FLAG: u8 = 0b1000_0000;
 
 /// offset  # of bytes  Function
 /// ----------------------------
@@ -35,14 +63,25 @@ use std::{ffi::CString, fs::read, path::Path};
 ///                 bit 5: if set, this song uses Sunsoft 5B audio
 ///                 bit 6: if set, this song uses VT02+ audio
 ///                 bit 7: reserved, must be zero
-/// $07C    1   BYTE    Reserved for NSF2
+/// $07C    1   BYTE    NSF2 flags (0 in version 1 files)
+///                 bits 0-3: reserved, must be 0
+///                 bit 4: if set, the program uses the IRQ timer at $401B-$401D
+///                 bit 5: if set, INIT never returns; PLAY is called like an NMI instead
+///                 bit 6: if set, PLAY is never called
+///                 bit 7: if set, metadata chunks follow the program data
 /// $07D    3   BYTE    24-bit length of contained program data.
 ///                 If 0, all data until end of file is part of the program.
 ///                 If used, can be used to provide NSF2 metadata
 ///                 in a backward compatible way.
 /// $080    nnn ----    The music program/data follows
+/// ...     nnn ----    NSF2 only, with bit 7 of the flags set: metadata chunks, in the same
+///                     format as NSFe's (read into `metadata` and `track_info`, with an auth
+///                     chunk overriding the text fields)
+///
+/// An NSFe file holds the same information in chunks instead, and is read into the same
+/// header; see `nsfe::read_nsfe`.
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct NsfHeader {
     pub version_num: u8,
     pub total_songs: u8,
@@ -50,28 +89,136 @@ pub struct NsfHeader {
     pub load_address: u16,
     pub init_address: u16,
     pub play_address: u16,
-    pub song_name: CString,
-    pub artist_name: CString,
-    pub copyright_holder: CString,
+    pub song_name: NsfText,
+    pub artist_name: NsfText,
+    pub copyright_holder: NsfText,
+    /// Who ripped the tune; only NSFe files say
+    pub ripper_name: NsfText,
     pub play_speed_ntsc: u16,
     pub bankswitch_init: [u8; 8],
     pub play_speed_pal: u16,
     pub pal_ntsc_bits: u8,
     pub sound_chip_support: u8,
-    pub nsf2_reserved: u8,
+    pub flags: u8,
     pub data_length: u32,
+    /// NSF2 metadata chunks, or an NSFe's chunks besides INFO and DATA
+    pub metadata: Vec<Chunk>,
+    /// Track names, times, fades and playlist from an NSFe's chunks or an NSF2's metadata
+    pub track_info: TrackInfo,
+    /// Problems reading the file that didn't stop it loading, which `lint` reports too
+    pub warnings: Vec<Lint>,
+}
+
+/// The timing an NSF is written for, from its header's `pal_ntsc_bits`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NsfRegion {
+    Ntsc,
+    Pal,
+    /// Either; the program adapts to whichever INIT is told it's running on
+    Dual,
 }
 
-fn trim_trailing_nuls(input: &[u8]) -> &[u8] {
-    let mut length = 0;
+impl NsfHeader {
+    /// bit 0 of `pal_ntsc_bits`: if set, this is a PAL tune
+    /// bit 1 of `pal_ntsc_bits`: if set, this is a dual PAL/NTSC tune
+    pub fn region(&self) -> NsfRegion {
+        if self.pal_ntsc_bits & 0b10 != 0 {
+            NsfRegion::Dual
+        } else if self.pal_ntsc_bits & 0b01 != 0 {
+            NsfRegion::Pal
+        } else {
+            NsfRegion::Ntsc
+        }
+    }
+
+    /// The chips flagged in `sound_chip_support`, leaving out the reserved bit
+    pub fn expansion_chips(&self) -> ExpansionChips {
+        ExpansionChips::from_bits_truncate(self.sound_chip_support)
+    }
+
+    /// How long there is between calls to PLAY when played back in `region`. Dendy consoles
+    /// run at 50 Hz like PAL ones, so they use the PAL play speed.
+    pub fn play_period(&self, region: Region) -> Duration {
+        let play_speed = match region {
+            Region::Ntsc => self.play_speed_ntsc,
+            Region::Pal | Region::Dendy => self.play_speed_pal,
+        };
+
+        Duration::from_micros(u64::from(play_speed))
+    }
+
+    /// How many times a second PLAY is called when played back in `region`
+    pub fn play_rate_hz(&self, region: Region) -> f64 {
+        1.0 / self.play_period(region).as_secs_f64()
+    }
 
-    for c in input {
-        if *c != 0 {
-            length += 1
+    /// The track numbers, counting from 1 like `starting_song`
+    pub fn tracks(&self) -> RangeInclusive<u8> {
+        1..=self.total_songs
+    }
+
+    /// The tracks in the order the metadata's playlist has them, leaving out any it doesn't list,
+    /// or all of them in order if there's no playlist. A playlist can have a track more than
+    /// once.
+    pub fn playlist(&self) -> Vec<u8> {
+        match &self.track_info.playlist {
+            Some(playlist) => playlist
+                .iter()
+                .filter_map(|&index| index.checked_add(1))
+                .filter(|track| self.tracks().contains(track))
+                .collect(),
+            None => self.tracks().collect(),
         }
     }
 
-    &input[..length]
+    /// The value INIT expects in the accumulator to play `track` (counting from 1), or `None`
+    /// if there's no such track
+    pub fn track_index(&self, track: u8) -> Option<u8> {
+        self.tracks().contains(&track).then(|| track - 1)
+    }
+
+    /// The name of `track` (counting from 1), if the metadata has one
+    pub fn track_label(&self, track: u8) -> Option<&str> {
+        let index = usize::from(self.track_index(track)?);
+
+        self.track_info.labels.get(index).map(String::as_str)
+    }
+
+    /// How long `track` (counting from 1) lasts, if the metadata says
+    pub fn track_time(&self, track: u8) -> Option<Duration> {
+        let index = usize::from(self.track_index(track)?);
+
+        self.track_info.times.get(index).copied().flatten()
+    }
+
+    /// How long `track` (counting from 1) takes to fade out, if the metadata says
+    pub fn track_fade(&self, track: u8) -> Option<Duration> {
+        let index = usize::from(self.track_index(track)?);
+
+        self.track_info.fades.get(index).copied().flatten()
+    }
+
+    /// Whether the program is split into 4 KiB banks, switched with $5FF8-$5FFF. A tune is
+    /// bankswitched if any of its `bankswitch_init` values are non-zero.
+    pub fn is_bankswitched(&self) -> bool {
+        self.bankswitch_init.iter().any(|&bank| bank != 0)
+    }
+}
+
+/// An NSFe-style chunk: a little-endian 32-bit length, a four-character ID and then the data
+///
+/// A chunk whose ID starts with an upper-case letter is one a player has to understand to play
+/// the file correctly; lower-case ones are safe to skip.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub id: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+impl Chunk {
+    pub fn is_mandatory(&self) -> bool {
+        self.id[0].is_ascii_uppercase()
+    }
 }
 
 fn parse_nsf(input: &[u8]) -> IResult<&[u8], NsfHeader> {
@@ -86,13 +233,16 @@ fn parse_nsf(input: &[u8]) -> IResult<&[u8], NsfHeader> {
     let (input, artist_name) = take(32usize)(input)?;
     let (input, copyright_holder) = take(32usize)(input)?;
     let (input, play_speed_ntsc) = le_u16(input)?;
-    let (input, bankswitch_init) = take(8usize)(input)?;
+    let (input, bankswitch_bytes) = take(8usize)(input)?;
     let (input, play_speed_pal) = le_u16(input)?;
     let (input, pal_ntsc_bits) = le_u8(input)?;
     let (input, sound_chip_support) = le_u8(input)?;
-    let (input, nsf2_reserved) = le_u8(input)?;
+    let (input, flags) = le_u8(input)?;
     let (input, data_length) = take(3usize)(input)?;
 
+    let mut bankswitch_init = [0; 8];
+    bankswitch_init.copy_from_slice(bankswitch_bytes);
+
     Ok((
         input,
         NsfHeader {
@@ -102,36 +252,242 @@ fn parse_nsf(input: &[u8]) -> IResult<&[u8], NsfHeader> {
             load_address,
             init_address,
             play_address,
-            song_name: CString::new(trim_trailing_nuls(song_name)).expect("bad song name"),
-            artist_name: CString::new(trim_trailing_nuls(artist_name)).expect("bad artist name"),
-            copyright_holder: CString::new(trim_trailing_nuls(copyright_holder))
-                .expect("bad copyright holder"),
+            song_name: NsfText::from_raw(song_name),
+            artist_name: NsfText::from_raw(artist_name),
+            copyright_holder: NsfText::from_raw(copyright_holder),
+            ripper_name: NsfText::default(),
             play_speed_ntsc,
-            bankswitch_init: bankswitch_init.try_into().unwrap(),
+            bankswitch_init,
             play_speed_pal,
             pal_ntsc_bits,
             sound_chip_support,
-            nsf2_reserved,
+            flags,
             data_length: u32::from_le_bytes([data_length[0], data_length[1], data_length[2], 0]),
+            metadata: vec![],
+            track_info: TrackInfo::default(),
+            warnings: vec![],
+        },
+    ))
+}
+
+fn parse_chunk(input: &[u8]) -> IResult<&[u8], Chunk> {
+    let (input, length) = le_u32(input)?;
+    let (input, id) = take(4usize)(input)?;
+    let (input, data) = take(length)(input)?;
+
+    Ok((
+        input,
+        Chunk {
+            id: [id[0], id[1], id[2], id[3]],
+            data: data.to_vec(),
         },
     ))
 }
 
-const SIXTEEN_K: usize = 16 * 2usize.pow(10);
+/// Chunks up to the end of `input` or an NEND chunk, whichever comes first, each with the offset
+/// in the file it starts at. `offset` is where `input` starts in the file.
+fn parse_chunks(mut input: &[u8], offset: usize) -> Result<Vec<(usize, Chunk)>> {
+    let end = offset + input.len();
+    let mut chunks = vec![];
+
+    while !input.is_empty() {
+        let chunk_offset = end - input.len();
+        let (rest, chunk) = parse_chunk(input).map_err(|_| NsfError::TruncatedChunk {
+            offset: chunk_offset,
+        })?;
+
+        input = rest;
+
+        if &chunk.id == b"NEND" {
+            break;
+        }
+
+        chunks.push((chunk_offset, chunk));
+    }
+
+    Ok(chunks)
+}
+
+/// Check that the load, init and play addresses (at `offsets` in the file) are somewhere the
+/// program can run from
+fn validate_addresses(header: &NsfHeader, offsets: [usize; 3]) -> Result<()> {
+    // FDS tunes can run from the RAM at $6000-$7FFF too
+    let lowest_address = if header.expansion_chips().contains(ExpansionChips::FDS) {
+        0x6000
+    } else {
+        0x8000
+    };
+
+    for (offset, field, address) in [
+        (offsets[0], "load address", header.load_address),
+        (offsets[1], "init address", header.init_address),
+        (offsets[2], "play address", header.play_address),
+    ] {
+        if address < lowest_address {
+            return Err(NsfError::InvalidAddress {
+                offset,
+                field,
+                address,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// The header and program data of an NSF or NSFe file's contents
+pub fn read_nsf(input: &[u8]) -> Result<(NsfHeader, Vec<u8>)> {
+    read(input, false)
+}
+
+/// Like `read_nsf`, but reads headers with a version or load, init or play addresses no player
+/// can cope with too, for `lint` to explain what's wrong with them. Only files that can't be
+/// parsed at all are refused.
+pub fn read_nsf_lenient(input: &[u8]) -> Result<(NsfHeader, Vec<u8>)> {
+    read(input, true)
+}
+
+fn read(input: &[u8], lenient: bool) -> Result<(NsfHeader, Vec<u8>)> {
+    if input.starts_with(b"NSFE") {
+        return nsfe::read_nsfe(input, lenient);
+    }
+
+    let (data, mut header) = parse_nsf(input).map_err(|_| {
+        let start = &input[..input.len().min(5)];
+
+        // A file that's too short to hold the whole magic is still truncated if what's there
+        // matches it
+        if b"NESM\x1a".starts_with(start) || b"NSFE".starts_with(start) {
+            NsfError::TruncatedHeader {
+                offset: input.len(),
+            }
+        } else {
+            NsfError::BadMagic {
+                offset: 0x000,
+                found: start.to_vec(),
+            }
+        }
+    })?;
+
+    if !lenient {
+        if !(1..=2).contains(&header.version_num) {
+            return Err(NsfError::UnsupportedVersion {
+                offset: 0x005,
+                version: header.version_num,
+            });
+        }
+
+        validate_addresses(&header, [0x008, 0x00A, 0x00C])?;
+    }
+
+    // Only NSF2 files flagged as having metadata have it after `data_length` bytes of program
+    // data; in anything else, the rest of the file is program data, whatever `data_length` says.
+    // A file cut short of `data_length` keeps whatever data it has, which `lint` points out.
+    let data_length = (header.data_length as usize).min(data.len());
+    let has_metadata =
+        header.version_num >= 2 && header.flags & METADATA_FLAG != 0 && data_length != 0;
+
+    let data = if has_metadata {
+        let (data, metadata) = data.split_at(data_length);
+
+        // Metadata that doesn't parse is no reason not to play the tune
+        if let Err(err) = read_metadata(&mut header, metadata, input.len() - metadata.len()) {
+            header.warnings.push(Lint {
+                severity: Severity::Warning,
+                field: "metadata",
+                explanation: format!("the metadata can't be read, so it's ignored: {err}"),
+            });
+        }
+
+        data
+    } else {
+        data
+    };
+
+    Ok((header, data.to_vec()))
+}
+
+/// Fill in `header` from the NSF2 metadata chunks in `metadata`, which starts at `offset` in the
+/// file. Nothing is filled in unless all of it can be read.
+fn read_metadata(header: &mut NsfHeader, metadata: &[u8], offset: usize) -> Result<()> {
+    let chunks = parse_chunks(metadata, offset)?;
+    let track_info = nsfe::parse_track_info(&chunks)?;
+    let authors = nsfe::parse_authors(&chunks)?;
+
+    header.track_info = track_info;
+
+    // auth has the names in full, where the header's fields stop at 31 bytes
+    if let Some(authors) = authors {
+        [
+            header.song_name,
+            header.artist_name,
+            header.copyright_holder,
+            header.ripper_name,
+        ] = authors;
+    }
+
+    header.metadata = chunks.into_iter().map(|(_, chunk)| chunk).collect();
+
+    Ok(())
+}
+
+/// The header and program data of the NSF or NSFe at `path`
+pub fn load_nsf<P: AsRef<Path>>(path: P) -> Result<(NsfHeader, Vec<u8>)> {
+    read_nsf(&fs::read(path)?)
+}
+
+/// The header and program data of the NSF or NSFe at `path`, read with `read_nsf_lenient`
+pub fn load_nsf_lenient<P: AsRef<Path>>(path: P) -> Result<(NsfHeader, Vec<u8>)> {
+    read_nsf_lenient(&fs::read(path)?)
+}
+
+const CART_BASE_ADDRESS: usize = 0x8000;
+const CART_PRG_SIZE: usize = 0x8000;
+
+/// An iNES image of a 32K NROM cartridge holding an unbankswitched NSF's program data at its
+/// load address, with anything outside $8000-$FFFF dropped. Dropping part of the data is
+/// reported as a warning.
+pub fn nsf_as_cart_data(header: &NsfHeader, data: &[u8]) -> (Vec<u8>, Vec<Lint>) {
+    let load_address = usize::from(header.load_address);
+    let end = load_address + data.len();
+    let mut warnings = vec![];
+
+    if load_address < CART_BASE_ADDRESS {
+        warnings.push(Lint {
+            severity: Severity::Warning,
+            field: "load_address",
+            explanation: format!(
+                "the {} bytes loaded below $8000 at ${load_address:04X} are in RAM, not the \
+                 cartridge, so they're dropped",
+                (CART_BASE_ADDRESS - load_address).min(data.len())
+            ),
+        });
+    }
 
-pub fn load_nsf_as_cart_data<P: AsRef<Path>>(path: P) -> Result<(NsfHeader, Vec<u8>)> {
-    let nsf_data = read(path)?;
+    if end > 0x10000 {
+        warnings.push(Lint {
+            severity: Severity::Warning,
+            field: "data",
+            explanation: format!(
+                "the last {} bytes run past $FFFF, so they're dropped",
+                end - 0x10000
+            ),
+        });
+    }
 
-    let header_and_data = parse_nsf(&nsf_data);
+    let mut prg_rom = vec![0; CART_PRG_SIZE];
 
-    let (data, header) = header_and_data.unwrap();
+    let skipped = CART_BASE_ADDRESS
+        .saturating_sub(load_address)
+        .min(data.len());
+    let start = load_address.max(CART_BASE_ADDRESS) - CART_BASE_ADDRESS;
+    let len = (data.len() - skipped).min(CART_PRG_SIZE.saturating_sub(start));
 
-    let zero_prepend_len = 16 * 2usize.pow(10) - data.len() % SIXTEEN_K;
-    let num_16k_units = data.len() / SIXTEEN_K + 1;
+    prg_rom[start..start + len].copy_from_slice(&data[skipped..skipped + len]);
 
     let mut rom_data: Vec<u8> = vec![];
     rom_data.append(&mut b"NES\x1a".to_vec());
-    rom_data.push(num_16k_units as u8);
+    rom_data.push((CART_PRG_SIZE / 0x4000) as u8); // size of PRG rom in 16K units
     rom_data.push(0u8); // size of CHR rom
     rom_data.push(0u8); // Flags 6
     rom_data.push(0u8); // Flags 7
@@ -139,8 +495,231 @@ pub fn load_nsf_as_cart_data<P: AsRef<Path>>(path: P) -> Result<(NsfHeader, Vec<
     rom_data.push(0u8); // Flags 9
     rom_data.push(0u8); // Flags 10
     rom_data.append(&mut [0u8; 5].to_vec()); // Padding
-    rom_data.append(&mut vec![0; zero_prepend_len]);
-    rom_data.append(&mut data.to_vec());
+    rom_data.append(&mut prg_rom);
+
+    (rom_data, warnings)
+}
+
+/// The header of the NSF at `path`, an iNES image of a cartridge holding its program data, and
+/// any warnings about data that didn't fit in the cartridge.
+///
+/// A bankswitched tune's image has its initial banks (from `bankswitch_init`) mapped in; to
+/// follow its later bank switches, run it against an `NsfMemory` instead.
+pub fn load_nsf_as_cart_data<P: AsRef<Path>>(path: P) -> Result<(NsfHeader, Vec<u8>, Vec<Lint>)> {
+    let (header, data) = load_nsf(path)?;
+
+    let (rom, warnings) = if header.is_bankswitched() {
+        (NsfMemory::new(&header, &data).ines_image(), vec![])
+    } else {
+        nsf_as_cart_data(&header, &data)
+    };
+
+    Ok((header, rom, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A one-track NSF header loading, initialising and playing at $8000, plus a byte of data
+    fn nsf_bytes() -> Vec<u8> {
+        let mut nsf = b"NESM\x1a\x01\x01\x01\x00\x80\x00\x80\x00\x80".to_vec();
+        nsf.resize(0x80, 0);
+        nsf.push(0x60);
+
+        nsf
+    }
+
+    #[test]
+    fn reads_nsf() {
+        let (header, data) = read_nsf(&nsf_bytes()).unwrap();
+
+        assert_eq!(header.version_num, 1);
+        assert_eq!(header.load_address, 0x8000);
+        assert_eq!(data, [0x60]);
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut nsf = nsf_bytes();
+        nsf[..5].copy_from_slice(b"RIFF\0");
+
+        let err = read_nsf(&nsf).unwrap_err();
+
+        assert!(matches!(
+            err,
+            NsfError::BadMagic { offset: 0x000, ref found } if found == b"RIFF\0"
+        ));
+    }
+
+    #[test]
+    fn truncated_header() {
+        let nsf = nsf_bytes();
+
+        for len in [3, 0x40, 0x7F] {
+            let err = read_nsf(&nsf[..len]).unwrap_err();
+
+            assert!(matches!(err, NsfError::TruncatedHeader { offset } if offset == len));
+        }
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut nsf = nsf_bytes();
+        nsf[0x005] = 3;
 
-    Ok((header, rom_data))
+        let err = read_nsf(&nsf).unwrap_err();
+
+        assert!(matches!(
+            err,
+            NsfError::UnsupportedVersion {
+                offset: 0x005,
+                version: 3
+            }
+        ));
+        assert_eq!(read_nsf_lenient(&nsf).unwrap().0.version_num, 3);
+    }
+
+    #[test]
+    fn invalid_address() {
+        for (offset, field) in [
+            (0x008, "load address"),
+            (0x00A, "init address"),
+            (0x00C, "play address"),
+        ] {
+            let mut nsf = nsf_bytes();
+            nsf[offset..offset + 2].copy_from_slice(&0x6000u16.to_le_bytes());
+
+            let err = read_nsf(&nsf).unwrap_err();
+
+            assert!(matches!(
+                err,
+                NsfError::InvalidAddress {
+                    offset: err_offset,
+                    field: err_field,
+                    address: 0x6000,
+                } if err_offset == offset && err_field == field
+            ));
+            assert!(read_nsf_lenient(&nsf).is_ok());
+
+            // An FDS tune can run from RAM
+            nsf[0x07B] = ExpansionChips::FDS.bits();
+            assert!(read_nsf(&nsf).is_ok());
+        }
+    }
+
+    /// `nsf_bytes` as an NSF2 with `flags`, a `data_length` of 1 and `metadata` after the data
+    fn nsf2_bytes(flags: u8, metadata: &[u8]) -> Vec<u8> {
+        let mut nsf = nsf_bytes();
+        nsf[0x005] = 2;
+        nsf[0x07C] = flags;
+        nsf[0x07D] = 1;
+        nsf.extend_from_slice(metadata);
+
+        nsf
+    }
+
+    /// A metadata chunk's bytes: its length, `id` and `data`
+    fn chunk_bytes(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+        chunk.extend_from_slice(id);
+        chunk.extend_from_slice(data);
+
+        chunk
+    }
+
+    #[test]
+    fn version_1_trailing_bytes_are_data() {
+        let mut nsf = nsf_bytes();
+        // Reserved in version 1, so a player can't take this to mean anything
+        nsf[0x07D] = 1;
+        nsf.extend_from_slice(b"garbage");
+
+        let (header, data) = read_nsf(&nsf).unwrap();
+
+        assert_eq!(data, b"\x60garbage");
+        assert!(header.metadata.is_empty());
+        assert!(header.warnings.is_empty());
+    }
+
+    #[test]
+    fn nsf2_metadata_needs_the_flag() {
+        let tlbl = chunk_bytes(b"tlbl", b"Title\0");
+
+        let (header, data) = read_nsf(&nsf2_bytes(0, &tlbl)).unwrap();
+
+        assert_eq!(data.len(), 1 + tlbl.len());
+        assert!(header.metadata.is_empty());
+
+        let (header, data) = read_nsf(&nsf2_bytes(METADATA_FLAG, &tlbl)).unwrap();
+
+        assert_eq!(data, [0x60]);
+        assert_eq!(header.track_info.labels, ["Title"]);
+    }
+
+    #[test]
+    fn unreadable_nsf2_metadata_is_dropped_with_a_warning() {
+        let mut metadata = chunk_bytes(b"auth", b"Song\0");
+        // A chunk claiming more bytes than there are
+        metadata.extend_from_slice(&[0xFF, 0, 0, 0]);
+        metadata.extend_from_slice(b"tlbl");
+
+        let (header, data) = read_nsf(&nsf2_bytes(METADATA_FLAG, &metadata)).unwrap();
+
+        assert_eq!(data, [0x60]);
+        assert!(header.metadata.is_empty());
+        // Not even the chunks before the bad one are used
+        assert!(header.song_name.is_empty());
+        assert_eq!(header.warnings.len(), 1);
+        assert_eq!(header.warnings[0].field, "metadata");
+        assert!(lint(&header, &data).contains(&header.warnings[0]));
+    }
+
+    /// The header from `nsf_bytes`, loading at `load_address`
+    fn header_loading_at(load_address: u16) -> NsfHeader {
+        let mut header = read_nsf(&nsf_bytes()).unwrap().0;
+        header.load_address = load_address;
+
+        header
+    }
+
+    #[test]
+    fn cart_data_is_placed_at_the_load_address() {
+        let (rom, warnings) = nsf_as_cart_data(&header_loading_at(0x8100), &[1, 2, 3]);
+
+        assert_eq!(&rom[..5], b"NES\x1a\x02");
+        assert_eq!(rom.len(), 0x10 + CART_PRG_SIZE);
+        assert_eq!(rom[0x10 + 0xFF], 0);
+        assert_eq!(rom[0x10 + 0x100..0x10 + 0x104], [1, 2, 3, 0]);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn cart_data_that_doesnt_fit_is_dropped_with_a_warning() {
+        let (rom, warnings) = nsf_as_cart_data(&header_loading_at(0x7FFE), &[1, 2, 3, 4]);
+
+        assert_eq!(rom[0x10..0x13], [3, 4, 0]);
+        assert_eq!(
+            warnings,
+            [Lint {
+                severity: Severity::Warning,
+                field: "load_address",
+                explanation: "the 2 bytes loaded below $8000 at $7FFE are in RAM, not the \
+                              cartridge, so they're dropped"
+                    .to_string(),
+            }]
+        );
+
+        let (rom, warnings) = nsf_as_cart_data(&header_loading_at(0xFFFE), &[1, 2, 3, 4]);
+
+        assert_eq!(rom[rom.len() - 2..], [1, 2]);
+        assert_eq!(
+            warnings,
+            [Lint {
+                severity: Severity::Warning,
+                field: "data",
+                explanation: "the last 2 bytes run past $FFFF, so they're dropped".to_string(),
+            }]
+        );
+    }
 }
@@ -0,0 +1,209 @@
+use std::{error::Error, ffi::NulError, fmt};
+
+use super::{write::MAX_TEXT_LEN, NsfHeader, NsfText};
+
+/// Why an edit to an `NsfHeader` was refused
+#[derive(Debug)]
+pub enum EditError {
+    /// A text field with a NUL in it
+    Nul(NulError),
+    /// Text longer than the header's text fields hold, in bytes
+    TooLong { len: usize },
+    /// A starting song that isn't one of the tune's songs
+    NoSuchSong { song: u8, total_songs: u8 },
+    /// A play speed of 0 ticks
+    ZeroPlaySpeed,
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EditError::Nul(err) => write!(f, "text can't contain a NUL: {err}"),
+            EditError::TooLong { len } => {
+                write!(
+                    f,
+                    "text is {len} bytes long, but can't be over {MAX_TEXT_LEN}"
+                )
+            }
+            EditError::NoSuchSong { song, total_songs } => {
+                write!(f, "there is no song {song} of {total_songs}")
+            }
+            EditError::ZeroPlaySpeed => write!(f, "the play speed can't be 0"),
+        }
+    }
+}
+
+impl Error for EditError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EditError::Nul(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<NulError> for EditError {
+    fn from(err: NulError) -> Self {
+        EditError::Nul(err)
+    }
+}
+
+/// Text for one of the header's text fields, which hold up to 31 bytes
+fn header_text(text: &str) -> Result<NsfText, EditError> {
+    if text.len() > MAX_TEXT_LEN {
+        return Err(EditError::TooLong { len: text.len() });
+    }
+
+    Ok(NsfText::new(text)?)
+}
+
+/// Fixes for mislabelled rips. Text has to fit in the 31 bytes an NSF header holds; longer names
+/// can still go in the fields directly (see `NsfText::new`) for an NSF2's or NSFe's auth chunk,
+/// and are cut down when written to a plain NSF.
+impl NsfHeader {
+    pub fn set_song_name(&mut self, song_name: &str) -> Result<(), EditError> {
+        self.song_name = header_text(song_name)?;
+        Ok(())
+    }
+
+    pub fn set_artist_name(&mut self, artist_name: &str) -> Result<(), EditError> {
+        self.artist_name = header_text(artist_name)?;
+        Ok(())
+    }
+
+    pub fn set_copyright_holder(&mut self, copyright_holder: &str) -> Result<(), EditError> {
+        self.copyright_holder = header_text(copyright_holder)?;
+        Ok(())
+    }
+
+    /// `song` counts from 1, like `starting_song`
+    pub fn set_starting_song(&mut self, song: u8) -> Result<(), EditError> {
+        if !(1..=self.total_songs).contains(&song) {
+            return Err(EditError::NoSuchSong {
+                song,
+                total_songs: self.total_songs,
+            });
+        }
+
+        self.starting_song = song;
+        Ok(())
+    }
+
+    /// In 1/1000000th sec ticks
+    pub fn set_play_speed_ntsc(&mut self, play_speed: u16) -> Result<(), EditError> {
+        if play_speed == 0 {
+            return Err(EditError::ZeroPlaySpeed);
+        }
+
+        self.play_speed_ntsc = play_speed;
+        Ok(())
+    }
+
+    /// In 1/1000000th sec ticks
+    pub fn set_play_speed_pal(&mut self, play_speed: u16) -> Result<(), EditError> {
+        if play_speed == 0 {
+            return Err(EditError::ZeroPlaySpeed);
+        }
+
+        self.play_speed_pal = play_speed;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nsf::{read_nsf, write_nsf};
+
+    const DATA: [u8; 1] = [0x60];
+
+    /// A three-track header loading, initialising and playing at $8000
+    fn header() -> NsfHeader {
+        let mut nsf = b"NESM\x1a\x01\x03\x01\x00\x80\x00\x80\x00\x80".to_vec();
+        nsf.resize(0x80, 0);
+
+        read_nsf(&nsf).unwrap().0
+    }
+
+    #[test]
+    fn text_has_to_fit_in_the_header() {
+        let mut header = header();
+        let longest = "a".repeat(MAX_TEXT_LEN);
+
+        assert!(header.set_song_name(&longest).is_ok());
+        assert_eq!(header.song_name.bytes(), longest.as_bytes());
+
+        let too_long = format!("{longest}é");
+
+        assert!(matches!(
+            header.set_song_name(&too_long),
+            Err(EditError::TooLong { len: 33 })
+        ));
+        assert!(matches!(
+            header.set_artist_name(&too_long),
+            Err(EditError::TooLong { len: 33 })
+        ));
+        assert!(matches!(
+            header.set_copyright_holder(&too_long),
+            Err(EditError::TooLong { len: 33 })
+        ));
+        assert!(matches!(
+            header.set_artist_name("Nul\0"),
+            Err(EditError::Nul(_))
+        ));
+
+        // Refused edits leave the fields alone
+        assert_eq!(header.song_name.bytes(), longest.as_bytes());
+        assert!(header.artist_name.is_empty());
+    }
+
+    #[test]
+    fn starting_song_has_to_exist() {
+        let mut header = header();
+
+        for song in [0, 4] {
+            assert!(matches!(
+                header.set_starting_song(song),
+                Err(EditError::NoSuchSong { song: err_song, total_songs: 3 }) if err_song == song
+            ));
+        }
+
+        assert!(header.set_starting_song(3).is_ok());
+        assert_eq!(header.starting_song, 3);
+    }
+
+    #[test]
+    fn play_speeds_cant_be_zero() {
+        let mut header = header();
+
+        assert!(matches!(
+            header.set_play_speed_ntsc(0),
+            Err(EditError::ZeroPlaySpeed)
+        ));
+        assert!(matches!(
+            header.set_play_speed_pal(0),
+            Err(EditError::ZeroPlaySpeed)
+        ));
+    }
+
+    #[test]
+    fn edits_survive_writing() {
+        let mut header = header();
+        header.set_song_name("Song").unwrap();
+        header.set_artist_name("Artist").unwrap();
+        header.set_copyright_holder("1986 Company").unwrap();
+        header.set_starting_song(2).unwrap();
+        header.set_play_speed_ntsc(16639).unwrap();
+        header.set_play_speed_pal(19997).unwrap();
+
+        let (read, data) = read_nsf(&write_nsf(&header, &DATA)).unwrap();
+
+        assert_eq!(read.song_name.bytes(), b"Song");
+        assert_eq!(read.artist_name.bytes(), b"Artist");
+        assert_eq!(read.copyright_holder.bytes(), b"1986 Company");
+        assert_eq!(read.starting_song, 2);
+        assert_eq!(read.play_speed_ntsc, 16639);
+        assert_eq!(read.play_speed_pal, 19997);
+        assert_eq!(data, DATA);
+    }
+}
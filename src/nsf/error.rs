@@ -0,0 +1,107 @@
+use std::{error::Error, fmt, io};
+
+/// Why an NSF couldn't be loaded. Every variant but `Io` carries the byte offset into the
+/// file where the problem is.
+#[derive(Debug)]
+pub enum NsfError {
+    Io(io::Error),
+    /// The file doesn't start with 'N','E','S','M',$1A (or 'N','S','F','E')
+    BadMagic {
+        offset: usize,
+        found: Vec<u8>,
+    },
+    /// The file ends before the 128-byte header does; `offset` is where it ran out
+    TruncatedHeader {
+        offset: usize,
+    },
+    UnsupportedVersion {
+        offset: usize,
+        version: u8,
+    },
+    /// A load, init or play address outside the memory the program can run from
+    InvalidAddress {
+        offset: usize,
+        field: &'static str,
+        address: u16,
+    },
+    /// A metadata chunk that runs past the end of the file; `offset` is where it starts
+    TruncatedChunk {
+        offset: usize,
+    },
+    /// An NSFe without one of the chunks every NSFe must have; `offset` is the end of the file
+    MissingChunk {
+        offset: usize,
+        id: [u8; 4],
+    },
+    /// A chunk that has to be understood to play the file, but isn't
+    UnsupportedChunk {
+        offset: usize,
+        id: [u8; 4],
+    },
+}
+
+impl NsfError {
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            NsfError::Io(_) => None,
+            NsfError::BadMagic { offset, .. }
+            | NsfError::TruncatedHeader { offset }
+            | NsfError::UnsupportedVersion { offset, .. }
+            | NsfError::InvalidAddress { offset, .. }
+            | NsfError::TruncatedChunk { offset }
+            | NsfError::MissingChunk { offset, .. }
+            | NsfError::UnsupportedChunk { offset, .. } => Some(*offset),
+        }
+    }
+}
+
+impl fmt::Display for NsfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NsfError::Io(err) => write!(f, "couldn't read NSF: {err}"),
+            NsfError::BadMagic { offset, found } => write!(
+                f,
+                "bad magic at offset {offset:#04X}: expected \"NESM\\x1A\" or \"NSFE\", found {found:02X?}"
+            ),
+            NsfError::TruncatedHeader { offset } => {
+                write!(f, "header truncated at offset {offset:#04X}")
+            }
+            NsfError::UnsupportedVersion { offset, version } => {
+                write!(f, "unsupported version {version} at offset {offset:#04X}")
+            }
+            NsfError::InvalidAddress {
+                offset,
+                field,
+                address,
+            } => write!(f, "invalid {field} ${address:04X} at offset {offset:#04X}"),
+            NsfError::TruncatedChunk { offset } => {
+                write!(f, "metadata chunk at offset {offset:#04X} is truncated")
+            }
+            NsfError::MissingChunk { offset, id } => write!(
+                f,
+                "no {} chunk before offset {offset:#04X}",
+                String::from_utf8_lossy(id)
+            ),
+            NsfError::UnsupportedChunk { offset, id } => write!(
+                f,
+                "unsupported mandatory chunk {} at offset {offset:#04X}",
+                String::from_utf8_lossy(id)
+            ),
+        }
+    }
+}
+
+impl Error for NsfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            NsfError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for NsfError {
+    fn from(err: io::Error) -> Self {
+        NsfError::Io(err)
+    }
+}
@@ -0,0 +1,119 @@
+/// The IRQ timer an NSF2 player provides to tunes with the IRQ flag set
+///
+/// $401B    Reload value low
+/// $401C    Reload value high
+/// $401D    ---- ---E    Enable (E). Any write restarts the count and acknowledges the IRQ.
+///
+/// While enabled, the timer counts down one per CPU cycle and raises the IRQ every time it
+/// passes zero, i.e. every reload + 1 cycles. The IRQ vector is whatever the program has
+/// written to $FFFE-$FFFF.
+#[derive(Debug, Clone, Default)]
+pub struct IrqTimer {
+    reload: u16,
+    counter: u16,
+    enabled: bool,
+    pending: bool,
+}
+
+impl IrqTimer {
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x401B => self.reload = self.reload & 0xFF00 | u16::from(val),
+            0x401C => self.reload = self.reload & 0x00FF | u16::from(val) << 8,
+            0x401D => {
+                self.enabled = val & 0b1 != 0;
+                self.counter = self.reload;
+                self.pending = false;
+            }
+            _ => (),
+        }
+    }
+
+    /// Run the timer for `cycles` CPU cycles
+    pub fn clock(&mut self, cycles: usize) {
+        if !self.enabled {
+            return;
+        }
+
+        let period = usize::from(self.reload) + 1;
+        let mut remaining = usize::from(self.counter) + 1;
+
+        if cycles >= remaining {
+            self.pending = true;
+            remaining = period - (cycles - remaining) % period;
+        } else {
+            remaining -= cycles;
+        }
+
+        self.counter = (remaining - 1) as u16;
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A timer enabled with `reload`
+    fn enabled(reload: u16) -> IrqTimer {
+        let mut timer = IrqTimer::default();
+        let [lo, hi] = reload.to_le_bytes();
+        timer.write(0x401B, lo);
+        timer.write(0x401C, hi);
+        timer.write(0x401D, 0b1);
+
+        timer
+    }
+
+    #[test]
+    fn fires_every_reload_plus_one_cycles() {
+        let mut timer = enabled(999);
+
+        timer.clock(999);
+        assert!(!timer.irq_pending());
+
+        timer.clock(1);
+        assert!(timer.irq_pending());
+
+        // Acknowledging restarts the count
+        timer.write(0x401D, 0b1);
+        assert!(!timer.irq_pending());
+
+        timer.clock(999);
+        assert!(!timer.irq_pending());
+        timer.clock(1);
+        assert!(timer.irq_pending());
+    }
+
+    #[test]
+    fn keeps_counting_past_an_irq() {
+        let mut timer = enabled(9);
+
+        // Past zero once, and 5 cycles into the next count
+        timer.clock(15);
+        assert!(timer.irq_pending());
+        assert_eq!(timer.counter, 4);
+
+        // In steps that don't line up with the period
+        let mut timer = enabled(9);
+
+        for _ in 0..7 {
+            timer.clock(3);
+        }
+
+        assert_eq!(timer.counter, 8);
+    }
+
+    #[test]
+    fn disabled_timer_doesnt_count() {
+        let mut timer = enabled(9);
+        timer.write(0x401D, 0);
+
+        timer.clock(100);
+        assert!(!timer.irq_pending());
+        assert_eq!(timer.counter, 9);
+    }
+}
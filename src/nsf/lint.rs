@@ -0,0 +1,533 @@
+use std::{cmp::Reverse, fmt};
+
+use crate::expansion::ExpansionChips;
+
+use super::{memory::BANK_SIZE, NsfHeader, NsfRegion, METADATA_FLAG, NO_PLAY_FLAG};
+
+/// Play speeds faster than this many ticks are more likely a mistake than a tune that really
+/// wants PLAY called over 1000 times a second
+const FASTEST_PLAUSIBLE_PLAY_SPEED: u16 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Odd, but a player can cope
+    Warning,
+    /// The tune won't play correctly, or at all
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem with a header, and which field it's in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    pub severity: Severity,
+    pub field: &'static str,
+    pub explanation: String,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.severity, self.field, self.explanation)
+    }
+}
+
+struct Lints(Vec<Lint>);
+
+impl Lints {
+    fn warn(&mut self, field: &'static str, explanation: String) {
+        self.0.push(Lint {
+            severity: Severity::Warning,
+            field,
+            explanation,
+        });
+    }
+
+    fn error(&mut self, field: &'static str, explanation: String) {
+        self.0.push(Lint {
+            severity: Severity::Error,
+            field,
+            explanation,
+        });
+    }
+}
+
+/// Everything that looks wrong with an NSF's header and program `data`, for screening rips.
+/// Errors come first.
+///
+/// Unlike `read_nsf`, which only refuses what it can't load at all, this also points out what
+/// the spec forbids or what no real tune would do. `read_nsf` refuses bad versions and
+/// addresses outright, so read rips with `read_nsf_lenient` to have those explained here too.
+pub fn lint(header: &NsfHeader, data: &[u8]) -> Vec<Lint> {
+    let mut lints = Lints(vec![]);
+
+    lint_version(header, &mut lints);
+    lint_songs(header, &mut lints);
+    lint_addresses(header, data, &mut lints);
+    lint_bankswitching(header, data, &mut lints);
+    lint_play_speeds(header, &mut lints);
+    lint_reserved_bits(header, &mut lints);
+
+    let mut lints = lints.0;
+    lints.extend(header.warnings.iter().cloned());
+    lints.sort_by_key(|lint| Reverse(lint.severity));
+
+    lints
+}
+
+fn lint_version(header: &NsfHeader, lints: &mut Lints) {
+    if !(1..=2).contains(&header.version_num) {
+        lints.error(
+            "version_num",
+            format!(
+                "version {} doesn't exist; only 1 and 2 (NSF2) do",
+                header.version_num
+            ),
+        );
+    }
+
+    if header.version_num < 2 && header.flags != 0 {
+        lints.warn(
+            "flags",
+            format!(
+                "flags are set to {:#04X}, but a version 1 player ignores them",
+                header.flags
+            ),
+        );
+    }
+}
+
+fn lint_songs(header: &NsfHeader, lints: &mut Lints) {
+    if header.total_songs == 0 {
+        lints.error("total_songs", "there are no songs".to_string());
+    }
+
+    if !(1..=header.total_songs.max(1)).contains(&header.starting_song) {
+        lints.error(
+            "starting_song",
+            format!(
+                "song {} doesn't exist; songs count from 1 and there are {}",
+                header.starting_song, header.total_songs
+            ),
+        );
+    }
+
+    let total_songs = usize::from(header.total_songs);
+    let track_info = &header.track_info;
+
+    if track_info.labels.len() > total_songs {
+        lints.warn(
+            "track_info",
+            format!(
+                "there are {} track names for {total_songs} songs",
+                track_info.labels.len()
+            ),
+        );
+    }
+
+    if let Some(playlist) = &track_info.playlist {
+        if let Some(track) = playlist
+            .iter()
+            .find(|&&track| usize::from(track) >= total_songs)
+        {
+            lints.error(
+                "track_info",
+                format!(
+                    "the playlist has song {}, but there are only {total_songs}",
+                    track + 1
+                ),
+            );
+        }
+    }
+}
+
+fn lint_addresses(header: &NsfHeader, data: &[u8], lints: &mut Lints) {
+    let fds = header.expansion_chips().contains(ExpansionChips::FDS);
+    let lowest_address = if fds { 0x6000 } else { 0x8000 };
+
+    let mut addresses = vec![
+        ("load_address", header.load_address),
+        ("init_address", header.init_address),
+    ];
+
+    if header.flags & NO_PLAY_FLAG == 0 || header.version_num < 2 {
+        addresses.push(("play_address", header.play_address));
+    }
+
+    for &(field, address) in &addresses {
+        if address < lowest_address {
+            lints.error(
+                field,
+                format!(
+                    "${address:04X} isn't in ${lowest_address:04X}-$FFFF, where {} programs run",
+                    if fds { "FDS" } else { "NSF" }
+                ),
+            );
+        }
+    }
+
+    if data.is_empty() {
+        lints.error("data", "there's no program data".to_string());
+        return;
+    }
+
+    if data.len() < header.data_length as usize {
+        lints.warn(
+            "data_length",
+            format!(
+                "the data is {} bytes long, but the file only has {}",
+                header.data_length,
+                data.len()
+            ),
+        );
+    }
+
+    // Bankswitched tunes' code can be in any bank, so only unbanked ones can be checked
+    if header.is_bankswitched() {
+        return;
+    }
+
+    let load_address = usize::from(header.load_address);
+    let end = load_address + data.len();
+
+    if end > 0x10000 {
+        lints.error(
+            "data",
+            format!(
+                "{} bytes loaded at ${load_address:04X} run {} bytes past $FFFF without \
+                 bankswitching",
+                data.len(),
+                end - 0x10000
+            ),
+        );
+    }
+
+    for &(field, address) in &addresses[1..] {
+        if !(load_address..end).contains(&usize::from(address)) {
+            lints.warn(
+                field,
+                format!(
+                    "${address:04X} is outside the program data at ${load_address:04X}-${:04X}",
+                    end.min(0x10000) - 1
+                ),
+            );
+        }
+    }
+}
+
+fn lint_bankswitching(header: &NsfHeader, data: &[u8], lints: &mut Lints) {
+    if !header.is_bankswitched() {
+        return;
+    }
+
+    let padding = usize::from(header.load_address) & (BANK_SIZE - 1);
+    let num_banks = (padding + data.len()).div_ceil(BANK_SIZE);
+
+    for (i, &bank) in header.bankswitch_init.iter().enumerate() {
+        if usize::from(bank) >= num_banks {
+            lints.warn(
+                "bankswitch_init",
+                format!(
+                    "bank {bank} for ${:04X} is past the {num_banks} banks of data, so it wraps \
+                     around to bank {}",
+                    0x8000 + i * BANK_SIZE,
+                    usize::from(bank) % num_banks.max(1)
+                ),
+            );
+        }
+    }
+}
+
+fn lint_play_speeds(header: &NsfHeader, lints: &mut Lints) {
+    if header.version_num >= 2 && header.flags & NO_PLAY_FLAG != 0 {
+        return;
+    }
+
+    let mut speeds = vec![];
+
+    if header.region() != NsfRegion::Pal {
+        speeds.push(("play_speed_ntsc", header.play_speed_ntsc));
+    }
+
+    if header.region() != NsfRegion::Ntsc {
+        speeds.push(("play_speed_pal", header.play_speed_pal));
+    }
+
+    for (field, speed) in speeds {
+        if speed == 0 {
+            lints.error(
+                field,
+                "PLAY can't be called every 0 microseconds".to_string(),
+            );
+        } else if speed < FASTEST_PLAUSIBLE_PLAY_SPEED {
+            lints.warn(
+                field,
+                format!(
+                    "{speed} microseconds calls PLAY {:.0} times a second, which is implausibly \
+                     fast",
+                    1_000_000.0 / f64::from(speed)
+                ),
+            );
+        }
+    }
+}
+
+fn lint_reserved_bits(header: &NsfHeader, lints: &mut Lints) {
+    if header.pal_ntsc_bits & !0b11 != 0 {
+        lints.warn(
+            "pal_ntsc_bits",
+            format!(
+                "reserved bits 2-7 must be 0, but are {:#04X}",
+                header.pal_ntsc_bits & !0b11
+            ),
+        );
+    }
+
+    if header.sound_chip_support & 0b1000_0000 != 0 {
+        lints.warn("sound_chip_support", "reserved bit 7 must be 0".to_string());
+    }
+
+    if header.expansion_chips().contains(ExpansionChips::VT02) {
+        lints.warn(
+            "sound_chip_support",
+            "VT02+ audio isn't supported, so the tune won't sound right".to_string(),
+        );
+    }
+
+    if header.version_num >= 2 {
+        if header.flags & 0b1111 != 0 {
+            lints.warn(
+                "flags",
+                format!(
+                    "reserved bits 0-3 must be 0, but are {:#04X}",
+                    header.flags & 0b1111
+                ),
+            );
+        }
+
+        if header.flags & METADATA_FLAG != 0 && header.data_length == 0 {
+            lints.warn(
+                "flags",
+                "the metadata flag is set, but with no data_length there's no metadata".to_string(),
+            );
+        }
+
+        if header.flags & METADATA_FLAG == 0 && header.data_length != 0 {
+            lints.warn(
+                "data_length",
+                "data_length is set, but without the metadata flag, what follows the data is \
+                 read as more data"
+                    .to_string(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nsf::{read_nsf_lenient, IRQ_FLAG};
+
+    const DATA: [u8; 1] = [0x60];
+
+    /// A one-track NTSC header loading, initialising and playing at $8000, with nothing wrong
+    /// with it
+    fn header() -> NsfHeader {
+        let mut nsf = b"NESM\x1a\x01\x01\x01\x00\x80\x00\x80\x00\x80".to_vec();
+        nsf.resize(0x80, 0);
+        nsf[0x06E..0x070].copy_from_slice(&16639u16.to_le_bytes());
+
+        read_nsf_lenient(&nsf).unwrap().0
+    }
+
+    /// Check that `header` and `DATA` give exactly `expected`, as (severity, field,
+    /// explanation), in order
+    fn assert_lints(header: &NsfHeader, expected: &[(Severity, &str, &str)]) {
+        let lints = lint(header, &DATA);
+        let lints: Vec<_> = lints
+            .iter()
+            .map(|lint| (lint.severity, lint.field, lint.explanation.as_str()))
+            .collect();
+
+        assert_eq!(lints, expected);
+    }
+
+    #[test]
+    fn clean_header() {
+        assert_lints(&header(), &[]);
+    }
+
+    #[test]
+    fn version() {
+        let mut header = header();
+        header.version_num = 3;
+
+        assert_lints(
+            &header,
+            &[(
+                Severity::Error,
+                "version_num",
+                "version 3 doesn't exist; only 1 and 2 (NSF2) do",
+            )],
+        );
+
+        header.version_num = 1;
+        header.flags = IRQ_FLAG;
+
+        assert_lints(
+            &header,
+            &[(
+                Severity::Warning,
+                "flags",
+                "flags are set to 0x10, but a version 1 player ignores them",
+            )],
+        );
+    }
+
+    #[test]
+    fn songs() {
+        let mut header = header();
+        header.total_songs = 0;
+
+        // Starting on song 1 isn't blamed too
+        assert_lints(
+            &header,
+            &[(Severity::Error, "total_songs", "there are no songs")],
+        );
+
+        header.total_songs = 2;
+        header.starting_song = 3;
+
+        assert_lints(
+            &header,
+            &[(
+                Severity::Error,
+                "starting_song",
+                "song 3 doesn't exist; songs count from 1 and there are 2",
+            )],
+        );
+    }
+
+    #[test]
+    fn addresses_below_8000() {
+        let mut header = header();
+        header.load_address = 0x7FFF;
+        header.init_address = 0x7FFF;
+        header.play_address = 0x7FFF;
+
+        assert_lints(
+            &header,
+            &[
+                (
+                    Severity::Error,
+                    "load_address",
+                    "$7FFF isn't in $8000-$FFFF, where NSF programs run",
+                ),
+                (
+                    Severity::Error,
+                    "init_address",
+                    "$7FFF isn't in $8000-$FFFF, where NSF programs run",
+                ),
+                (
+                    Severity::Error,
+                    "play_address",
+                    "$7FFF isn't in $8000-$FFFF, where NSF programs run",
+                ),
+            ],
+        );
+
+        // FDS tunes run from RAM at $6000 up
+        header.sound_chip_support = ExpansionChips::FDS.bits();
+
+        assert_lints(&header, &[]);
+    }
+
+    #[test]
+    fn banks_past_the_data() {
+        let mut header = header();
+        header.bankswitch_init = [0, 1, 0, 0, 0, 0, 0, 2];
+
+        assert_lints(
+            &header,
+            &[
+                (
+                    Severity::Warning,
+                    "bankswitch_init",
+                    "bank 1 for $9000 is past the 1 banks of data, so it wraps around to bank 0",
+                ),
+                (
+                    Severity::Warning,
+                    "bankswitch_init",
+                    "bank 2 for $F000 is past the 1 banks of data, so it wraps around to bank 0",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn zero_play_speeds() {
+        let mut header = header();
+        header.play_speed_ntsc = 0;
+        // Dual-region tunes need both
+        header.pal_ntsc_bits = 0b10;
+
+        assert_lints(
+            &header,
+            &[
+                (
+                    Severity::Error,
+                    "play_speed_ntsc",
+                    "PLAY can't be called every 0 microseconds",
+                ),
+                (
+                    Severity::Error,
+                    "play_speed_pal",
+                    "PLAY can't be called every 0 microseconds",
+                ),
+            ],
+        );
+
+        // A tune that never has PLAY called doesn't need a speed
+        header.version_num = 2;
+        header.flags = NO_PLAY_FLAG;
+
+        assert_lints(&header, &[]);
+    }
+
+    #[test]
+    fn reserved_bits() {
+        let mut header = header();
+        header.version_num = 2;
+        header.pal_ntsc_bits = 0b100;
+        header.sound_chip_support = 0b1000_0000;
+        header.flags = 0b1;
+
+        assert_lints(
+            &header,
+            &[
+                (
+                    Severity::Warning,
+                    "pal_ntsc_bits",
+                    "reserved bits 2-7 must be 0, but are 0x04",
+                ),
+                (
+                    Severity::Warning,
+                    "sound_chip_support",
+                    "reserved bit 7 must be 0",
+                ),
+                (
+                    Severity::Warning,
+                    "flags",
+                    "reserved bits 0-3 must be 0, but are 0x01",
+                ),
+            ],
+        );
+    }
+}
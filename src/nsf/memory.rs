@@ -0,0 +1,434 @@
+use std::ops::RangeInclusive;
+
+use crate::expansion::ExpansionChips;
+
+use super::{Lint, NsfHeader, Severity, IRQ_FLAG};
+
+pub const BANK_SIZE: usize = 0x1000;
+
+/// Where the memory this models starts
+const BASE_ADDRESS: u16 = 0x6000;
+
+const EXRAM_ADDRESS: u16 = 0x5C00;
+const EXRAM_SIZE: usize = 0x3F6;
+
+/// The cartridge side of an NSF player: the $6000-$FFFF address space an NSF's program runs
+/// in, and the mapper registers below it.
+///
+/// $6000-$7FFF is always RAM. An FDS tune gets the disk system's RAM adapter on top of that,
+/// making $6000-$DFFF writable; $E000-$FFFF stays read-only.
+///
+/// A tune that isn't bankswitched has its data placed at its load address, with anything below
+/// $6000 or past $FFFF dropped (see `warnings`). A bankswitched tune's data is padded at the
+/// front by `load_address & 0x0FFF` bytes and split into 4 KiB banks, and writing a bank number
+/// to one of the bank registers maps that bank in:
+///
+/// $5FF6    $6000-$6FFF (FDS only)
+/// $5FF7    $7000-$7FFF (FDS only)
+/// $5FF8    $8000-$8FFF
+/// ...
+/// $5FFF    $F000-$FFFF
+///
+/// An FDS tune's banks land in RAM, so switching one in copies it over whatever was there.
+///
+/// An MMC5 tune also gets the MMC5's ExRAM at $5C00-$5FF5, as plain RAM, and its multiplier:
+/// write the factors to $5205 and $5206 and read the 16-bit product back from them. An NSF2
+/// tune with the IRQ flag set can write its IRQ vector to $FFFE-$FFFF.
+///
+/// The expansion chips' sound registers aren't here; `NsfDriver` hands every write to the
+/// `Apu` as well.
+#[derive(Debug, Clone)]
+pub struct NsfMemory {
+    fds: bool,
+    mmc5: bool,
+    irq_vector_writable: bool,
+    /// The padded program data, if the tune is bankswitched
+    banks: Option<Vec<u8>>,
+    /// $6000-$FFFF as the CPU sees it
+    space: Vec<u8>,
+    /// The MMC5's ExRAM
+    exram: Vec<u8>,
+    /// The factors written to the MMC5's $5205 and $5206
+    multiplier: [u8; 2],
+    warnings: Vec<Lint>,
+}
+
+impl NsfMemory {
+    pub fn new(header: &NsfHeader, data: &[u8]) -> Self {
+        let fds = header.expansion_chips().contains(ExpansionChips::FDS);
+        let mmc5 = header.expansion_chips().contains(ExpansionChips::MMC5);
+        let bankswitched = header.is_bankswitched();
+
+        let mut memory = NsfMemory {
+            fds,
+            mmc5,
+            irq_vector_writable: header.flags & IRQ_FLAG != 0,
+            banks: None,
+            space: vec![0; 0x10000 - usize::from(BASE_ADDRESS)],
+            exram: vec![0; EXRAM_SIZE],
+            multiplier: [0; 2],
+            warnings: vec![],
+        };
+
+        if bankswitched {
+            let padding = usize::from(header.load_address) & (BANK_SIZE - 1);
+
+            // With no data at all there's still one (empty) bank, so there's something to switch
+            // in
+            let mut banks = vec![0; padding];
+            banks.extend_from_slice(data);
+            banks.resize(banks.len().next_multiple_of(BANK_SIZE).max(BANK_SIZE), 0);
+
+            memory.banks = Some(banks);
+
+            // The FDS registers start out with the values in the last two bankswitch bytes
+            if fds {
+                memory.write(0x5FF6, header.bankswitch_init[6]);
+                memory.write(0x5FF7, header.bankswitch_init[7]);
+            }
+
+            for (addr, bank) in (0x5FF8..=0x5FFF).zip(header.bankswitch_init) {
+                memory.write(addr, bank);
+            }
+        } else {
+            let load_address = usize::from(header.load_address);
+            let end = load_address + data.len();
+            let base = usize::from(BASE_ADDRESS);
+
+            if load_address < base {
+                memory.warnings.push(Lint {
+                    severity: Severity::Warning,
+                    field: "load_address",
+                    explanation: format!(
+                        "the {} bytes loaded below $6000 at ${load_address:04X} have nowhere to \
+                         go, so they're dropped",
+                        (base - load_address).min(data.len())
+                    ),
+                });
+            }
+
+            if end > 0x10000 {
+                memory.warnings.push(Lint {
+                    severity: Severity::Warning,
+                    field: "data",
+                    explanation: format!(
+                        "the last {} bytes run past $FFFF, so they're dropped",
+                        end - 0x10000
+                    ),
+                });
+            }
+
+            let skipped = base.saturating_sub(load_address).min(data.len());
+            let start = load_address.max(base) - base;
+            let len = (data.len() - skipped).min(memory.space.len() - start);
+
+            memory.space[start..start + len].copy_from_slice(&data[skipped..skipped + len]);
+        }
+
+        memory
+    }
+
+    pub fn is_fds(&self) -> bool {
+        self.fds
+    }
+
+    pub fn is_mmc5(&self) -> bool {
+        self.mmc5
+    }
+
+    /// Data that didn't fit in memory and was dropped
+    pub fn warnings(&self) -> &[Lint] {
+        &self.warnings
+    }
+
+    /// A CPU read, or `None` if nothing here responds to `addr`
+    pub fn read(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5205 if self.mmc5 => Some(self.product()[0]),
+            0x5206 if self.mmc5 => Some(self.product()[1]),
+            0x5C00..=0x5FF5 if self.mmc5 => Some(self.exram[usize::from(addr - EXRAM_ADDRESS)]),
+            0x6000..=0xFFFF => Some(self.space[usize::from(addr - BASE_ADDRESS)]),
+            _ => None,
+        }
+    }
+
+    fn product(&self) -> [u8; 2] {
+        let [a, b] = self.multiplier;
+
+        (u16::from(a) * u16::from(b)).to_le_bytes()
+    }
+
+    /// A CPU write. Returns the addresses in $6000-$FFFF it changed other than by writing to
+    /// $6000-$7FFF, if any: the 4 KiB a bank switch maps in, or the byte written to the FDS's
+    /// RAM above $7FFF or the IRQ vector.
+    pub fn write(&mut self, addr: u16, val: u8) -> Option<RangeInclusive<u16>> {
+        match addr {
+            0x5205..=0x5206 if self.mmc5 => {
+                self.multiplier[usize::from(addr - 0x5205)] = val;
+                None
+            }
+            0x5C00..=0x5FF5 if self.mmc5 => {
+                self.exram[usize::from(addr - EXRAM_ADDRESS)] = val;
+                None
+            }
+            0x5FF6..=0x5FF7 if self.fds => self.switch_bank(addr, val),
+            0x5FF8..=0x5FFF => self.switch_bank(addr, val),
+            0x6000..=0x7FFF => {
+                self.space[usize::from(addr - BASE_ADDRESS)] = val;
+                None
+            }
+            0x8000..=0xDFFF if self.fds => {
+                self.space[usize::from(addr - BASE_ADDRESS)] = val;
+                Some(addr..=addr)
+            }
+            0xFFFE..=0xFFFF if self.irq_vector_writable => {
+                self.space[usize::from(addr - BASE_ADDRESS)] = val;
+                Some(addr..=addr)
+            }
+            _ => None,
+        }
+    }
+
+    fn switch_bank(&mut self, register: u16, bank: u8) -> Option<RangeInclusive<u16>> {
+        let banks = self.banks.as_ref()?;
+
+        // Bank numbers past the end of the data wrap around
+        let num_banks = banks.len() / BANK_SIZE;
+        let start = usize::from(bank) % num_banks * BANK_SIZE;
+
+        let region = usize::from(register - 0x5FF6) * BANK_SIZE;
+
+        self.space[region..region + BANK_SIZE].copy_from_slice(&banks[start..start + BANK_SIZE]);
+
+        let first = BASE_ADDRESS + region as u16;
+
+        Some(first..=first + (BANK_SIZE as u16 - 1))
+    }
+
+    /// The bytes mapped at `addrs`, which must be in $6000-$FFFF
+    pub fn slice(&self, addrs: RangeInclusive<u16>) -> &[u8] {
+        let start = usize::from(addrs.start() - BASE_ADDRESS);
+        let end = usize::from(addrs.end() - BASE_ADDRESS);
+
+        &self.space[start..=end]
+    }
+
+    /// $6000-$7FFF
+    pub fn prg_ram(&self) -> &[u8] {
+        &self.space[..0x2000]
+    }
+
+    /// $8000-$FFFF
+    pub fn prg_rom(&self) -> &[u8] {
+        &self.space[0x2000..]
+    }
+
+    /// An iNES image of a 32K NROM cartridge holding what's currently mapped at $8000-$FFFF
+    pub fn ines_image(&self) -> Vec<u8> {
+        let mut rom_data: Vec<u8> = vec![];
+        rom_data.append(&mut b"NES\x1a".to_vec());
+        rom_data.push(2u8); // size of PRG rom in 16K units
+        rom_data.push(0u8); // size of CHR rom
+        rom_data.push(0u8); // Flags 6
+        rom_data.append(&mut [0u8; 9].to_vec()); // Flags 7-10, padding
+        rom_data.extend_from_slice(self.prg_rom());
+
+        rom_data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nsf::read_nsf_lenient;
+
+    const NO_BANKS: [u8; 8] = [0; 8];
+
+    /// A header loading at `load_address` with the expansion `chips` and `bankswitch_init`
+    fn header(load_address: u16, chips: ExpansionChips, bankswitch_init: [u8; 8]) -> NsfHeader {
+        let mut nsf = b"NESM\x1a\x02\x01\x01".to_vec();
+        nsf.resize(0x80, 0);
+
+        let mut header = read_nsf_lenient(&nsf).unwrap().0;
+        header.load_address = load_address;
+        header.sound_chip_support = chips.bits();
+        header.bankswitch_init = bankswitch_init;
+
+        header
+    }
+
+    /// `num_banks` banks of data, with every byte holding its bank's number
+    fn banked_data(num_banks: u8) -> Vec<u8> {
+        (0..num_banks).flat_map(|bank| [bank; BANK_SIZE]).collect()
+    }
+
+    #[test]
+    fn data_goes_at_the_load_address() {
+        let memory = NsfMemory::new(
+            &header(0x8123, ExpansionChips::empty(), NO_BANKS),
+            &[1, 2, 3],
+        );
+
+        assert_eq!(memory.read(0x8122), Some(0));
+        assert_eq!(memory.slice(0x8123..=0x8125), [1, 2, 3]);
+        assert_eq!(memory.read(0x8126), Some(0));
+        assert!(memory.warnings().is_empty());
+    }
+
+    #[test]
+    fn data_that_doesnt_fit_is_dropped_with_a_warning() {
+        let memory = NsfMemory::new(
+            &header(0x5FFE, ExpansionChips::empty(), NO_BANKS),
+            &[1, 2, 3, 4],
+        );
+
+        assert_eq!(memory.slice(0x6000..=0x6002), [3, 4, 0]);
+        assert_eq!(
+            memory.warnings(),
+            [Lint {
+                severity: Severity::Warning,
+                field: "load_address",
+                explanation: "the 2 bytes loaded below $6000 at $5FFE have nowhere to go, so \
+                              they're dropped"
+                    .to_string(),
+            }]
+        );
+
+        let memory = NsfMemory::new(
+            &header(0xFFFE, ExpansionChips::empty(), NO_BANKS),
+            &[1, 2, 3, 4],
+        );
+
+        assert_eq!(memory.slice(0xFFFE..=0xFFFF), [1, 2]);
+        assert_eq!(
+            memory.warnings(),
+            [Lint {
+                severity: Severity::Warning,
+                field: "data",
+                explanation: "the last 2 bytes run past $FFFF, so they're dropped".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn banks_are_padded_to_the_load_address() {
+        let data: Vec<_> = (0..=0xFF).collect();
+        let memory = NsfMemory::new(
+            &header(0x8FF0, ExpansionChips::empty(), [0, 1, 0, 0, 0, 0, 0, 0]),
+            &data,
+        );
+
+        // The first 0xFF0 bytes of bank 0 are padding
+        assert_eq!(memory.read(0x8000), Some(0));
+        assert_eq!(memory.slice(0x8FF0..=0x8FFF), &data[..0x10]);
+        assert_eq!(memory.slice(0x9000..=0x9003), &data[0x10..0x14]);
+    }
+
+    #[test]
+    fn bank_switching() {
+        let mut memory = NsfMemory::new(
+            &header(0x8000, ExpansionChips::empty(), [0, 1, 2, 0, 0, 0, 0, 0]),
+            &banked_data(3),
+        );
+
+        assert_eq!(memory.read(0x8000), Some(0));
+        assert_eq!(memory.read(0x9000), Some(1));
+        assert_eq!(memory.read(0xAFFF), Some(2));
+
+        assert_eq!(memory.write(0x5FFF, 2), Some(0xF000..=0xFFFF));
+        assert!(memory.slice(0xF000..=0xFFFF).iter().all(|&byte| byte == 2));
+
+        // Past the last bank, the numbers wrap around
+        assert_eq!(memory.write(0x5FF8, 4), Some(0x8000..=0x8FFF));
+        assert!(memory.slice(0x8000..=0x8FFF).iter().all(|&byte| byte == 1));
+
+        // ROM isn't writable
+        assert_eq!(memory.write(0x8000, 0xFF), None);
+        assert_eq!(memory.read(0x8000), Some(1));
+    }
+
+    #[test]
+    fn unbanked_tunes_ignore_bank_switches() {
+        let mut memory = NsfMemory::new(
+            &header(0x8000, ExpansionChips::empty(), NO_BANKS),
+            &banked_data(2),
+        );
+
+        assert_eq!(memory.write(0x5FF8, 1), None);
+        assert_eq!(memory.read(0x8000), Some(0));
+    }
+
+    #[test]
+    fn fds_banks_and_ram() {
+        let banks = [0, 1, 2, 3, 0, 0, 1, 2];
+        let mut memory =
+            NsfMemory::new(&header(0x8000, ExpansionChips::FDS, banks), &banked_data(4));
+
+        // The last two bankswitch bytes start out in $6000-$7FFF
+        assert_eq!(memory.read(0x6000), Some(1));
+        assert_eq!(memory.read(0x7000), Some(2));
+
+        assert_eq!(memory.write(0x5FF6, 3), Some(0x6000..=0x6FFF));
+        assert_eq!(memory.read(0x6FFF), Some(3));
+
+        assert_eq!(memory.write(0x8000, 0xAA), Some(0x8000..=0x8000));
+        assert_eq!(memory.write(0xDFFF, 0xBB), Some(0xDFFF..=0xDFFF));
+        assert_eq!(memory.read(0x8000), Some(0xAA));
+        assert_eq!(memory.read(0xDFFF), Some(0xBB));
+
+        // $E000-$FFFF isn't RAM
+        assert_eq!(memory.write(0xE000, 0xCC), None);
+        assert_eq!(memory.read(0xE000), Some(1));
+
+        // Without the FDS, there are no $5FF6 and $5FF7
+        let mut memory = NsfMemory::new(
+            &header(0x8000, ExpansionChips::empty(), banks),
+            &banked_data(4),
+        );
+
+        assert_eq!(memory.write(0x5FF6, 3), None);
+        assert_eq!(memory.read(0x6000), Some(0));
+        assert_eq!(memory.write(0x8000, 0xAA), None);
+    }
+
+    #[test]
+    fn writable_irq_vector() {
+        let mut header = header(0x8000, ExpansionChips::empty(), NO_BANKS);
+        let mut memory = NsfMemory::new(&header, &[]);
+
+        assert_eq!(memory.write(0xFFFE, 0x34), None);
+        assert_eq!(memory.read(0xFFFE), Some(0));
+
+        header.flags = IRQ_FLAG;
+        let mut memory = NsfMemory::new(&header, &[]);
+
+        assert_eq!(memory.write(0xFFFE, 0x34), Some(0xFFFE..=0xFFFE));
+        assert_eq!(memory.write(0xFFFF, 0x12), Some(0xFFFF..=0xFFFF));
+        assert_eq!(memory.slice(0xFFFE..=0xFFFF), [0x34, 0x12]);
+        // Only the vector
+        assert_eq!(memory.write(0xFFFD, 0x56), None);
+    }
+
+    #[test]
+    fn mmc5_exram_and_multiplier() {
+        let mut memory = NsfMemory::new(&header(0x8000, ExpansionChips::MMC5, NO_BANKS), &[]);
+
+        assert_eq!(memory.write(0x5C00, 0x12), None);
+        assert_eq!(memory.write(0x5FF5, 0x34), None);
+        assert_eq!(memory.read(0x5C00), Some(0x12));
+        assert_eq!(memory.read(0x5FF5), Some(0x34));
+
+        memory.write(0x5205, 200);
+        memory.write(0x5206, 100);
+
+        // 20000 = $4E20
+        assert_eq!(memory.read(0x5205), Some(0x20));
+        assert_eq!(memory.read(0x5206), Some(0x4E));
+
+        let mut memory = NsfMemory::new(&header(0x8000, ExpansionChips::empty(), NO_BANKS), &[]);
+
+        memory.write(0x5C00, 0x12);
+        assert_eq!(memory.read(0x5C00), None);
+        assert_eq!(memory.read(0x5205), None);
+    }
+}
@@ -0,0 +1,375 @@
+use nom::{
+    bytes::complete::{tag, take_till},
+    combinator::opt,
+    multi::many0,
+    number::complete::{le_i32, le_u16, le_u8},
+    sequence::terminated,
+    IResult,
+};
+use std::time::Duration;
+
+use super::{parse_chunks, validate_addresses, Chunk, NsfError, NsfHeader, NsfText, Result};
+
+/// The defaults for tunes without a RATE chunk: 60.0988 Hz and 50.0070 Hz
+const DEFAULT_PLAY_SPEED_NTSC: u16 = 16639;
+const DEFAULT_PLAY_SPEED_PAL: u16 = 19997;
+
+/// The mandatory chunks this parser knows what to do with
+const KNOWN_MANDATORY_CHUNKS: [&[u8; 4]; 5] = [b"INFO", b"DATA", b"BANK", b"RATE", b"NSF2"];
+
+/// What an NSFe's (or an NSF2's) optional chunks say about the individual tracks. Every list is
+/// indexed by track number, counting from 0, and may be shorter than the number of tracks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackInfo {
+    /// tlbl: track names
+    pub labels: Vec<String>,
+    /// time: how long each track lasts, if it says
+    pub times: Vec<Option<Duration>>,
+    /// fade: how long each track takes to fade out at the end, if it says
+    pub fades: Vec<Option<Duration>>,
+    /// plst: the order to play the tracks in, as track numbers counting from 0
+    pub playlist: Option<Vec<u8>>,
+}
+
+/// The INFO chunk
+///
+/// offset  # of bytes  Function
+/// ----------------------------
+/// $00     2   WORD    (lo, hi) load address
+/// $02     2   WORD    (lo, hi) init address
+/// $04     2   WORD    (lo, hi) play address
+/// $06     1   BYTE    PAL/NTSC bits, as in an NSF
+/// $07     1   BYTE    Extra sound chip support, as in an NSF
+/// $08     1   BYTE    Total songs (optional, 1 if missing)
+/// $09     1   BYTE    Starting song, counting from 0 (optional, 0 if missing)
+struct Info {
+    load_address: u16,
+    init_address: u16,
+    play_address: u16,
+    pal_ntsc_bits: u8,
+    sound_chip_support: u8,
+    total_songs: u8,
+    starting_song: u8,
+}
+
+fn parse_info(input: &[u8]) -> IResult<&[u8], Info> {
+    let (input, load_address) = le_u16(input)?;
+    let (input, init_address) = le_u16(input)?;
+    let (input, play_address) = le_u16(input)?;
+    let (input, pal_ntsc_bits) = le_u8(input)?;
+    let (input, sound_chip_support) = le_u8(input)?;
+    let (input, total_songs) = opt(le_u8)(input)?;
+    let (input, starting_song) = opt(le_u8)(input)?;
+
+    Ok((
+        input,
+        Info {
+            load_address,
+            init_address,
+            play_address,
+            pal_ntsc_bits,
+            sound_chip_support,
+            total_songs: total_songs.unwrap_or(1),
+            starting_song: starting_song.unwrap_or(0),
+        },
+    ))
+}
+
+/// The RATE chunk: NTSC play speed, then optionally PAL and Dendy play speeds, in the same
+/// 1/1000000th sec ticks as an NSF's
+fn parse_rate(input: &[u8]) -> IResult<&[u8], (u16, Option<u16>)> {
+    let (input, ntsc) = le_u16(input)?;
+    let (input, pal) = opt(le_u16)(input)?;
+
+    Ok((input, (ntsc, pal)))
+}
+
+/// NUL-terminated strings, as in tlbl and auth. A missing final NUL is allowed.
+fn parse_strings(input: &[u8]) -> IResult<&[u8], Vec<&[u8]>> {
+    let (input, mut strings) = many0(terminated(take_till(|c| c == 0), tag(b"\0")))(input)?;
+
+    if !input.is_empty() {
+        strings.push(input);
+    }
+
+    Ok((&[], strings))
+}
+
+/// time and fade: a signed 32-bit number of milliseconds per track. Negative means the track
+/// doesn't say.
+fn parse_durations(input: &[u8]) -> IResult<&[u8], Vec<Option<Duration>>> {
+    let (input, millis) = many0(le_i32)(input)?;
+
+    let durations = millis
+        .into_iter()
+        .map(|millis| u64::try_from(millis).ok().map(Duration::from_millis))
+        .collect();
+
+    Ok((input, durations))
+}
+
+/// Run a chunk's parser over its data, reporting a chunk too short for it as truncated
+fn parse_chunk_data<'a, T>(
+    parser: impl FnOnce(&'a [u8]) -> IResult<&'a [u8], T>,
+    chunk: &'a Chunk,
+    offset: usize,
+) -> Result<T> {
+    parser(&chunk.data)
+        .map(|(_, parsed)| parsed)
+        .map_err(|_| NsfError::TruncatedChunk { offset })
+}
+
+fn lossy_string(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// What the optional chunks among `chunks` (each with the offset it starts at) say about the
+/// tracks
+pub(super) fn parse_track_info(chunks: &[(usize, Chunk)]) -> Result<TrackInfo> {
+    let mut track_info = TrackInfo::default();
+
+    for (offset, chunk) in chunks {
+        match &chunk.id {
+            b"tlbl" => {
+                track_info.labels = parse_chunk_data(parse_strings, chunk, *offset)?
+                    .into_iter()
+                    .map(lossy_string)
+                    .collect();
+            }
+            b"time" => track_info.times = parse_chunk_data(parse_durations, chunk, *offset)?,
+            b"fade" => track_info.fades = parse_chunk_data(parse_durations, chunk, *offset)?,
+            b"plst" => track_info.playlist = Some(chunk.data.clone()),
+            _ => (),
+        }
+    }
+
+    Ok(track_info)
+}
+
+/// The game title, artist, copyright holder and ripper from the auth chunk among `chunks`, if
+/// there is one. Missing ones are left empty.
+pub(super) fn parse_authors(chunks: &[(usize, Chunk)]) -> Result<Option<[NsfText; 4]>> {
+    let Some((offset, auth)) = chunks.iter().find(|(_, chunk)| &chunk.id == b"auth") else {
+        return Ok(None);
+    };
+
+    let mut authors = parse_chunk_data(parse_strings, auth, *offset)?
+        .into_iter()
+        .map(NsfText::from_raw);
+
+    Ok(Some([(); 4].map(|_| authors.next().unwrap_or_default())))
+}
+
+/// The header and program data of an NSFe file's contents, which start with 'N','S','F','E'
+/// followed by chunks up to an NEND chunk.
+///
+/// INFO and DATA are required; BANK, RATE and auth fill in the rest of the header, the NSF2
+/// chunk (if any) holds its flags, and the remaining chunks are kept in `metadata`. A `lenient`
+/// read doesn't check the addresses.
+pub(super) fn read_nsfe(input: &[u8], lenient: bool) -> Result<(NsfHeader, Vec<u8>)> {
+    let chunks = parse_chunks(&input[4..], 4)?;
+
+    let find = |id: &[u8; 4]| chunks.iter().find(|(_, chunk)| &chunk.id == id);
+
+    if let Some((offset, chunk)) = chunks
+        .iter()
+        .find(|(_, chunk)| chunk.is_mandatory() && !KNOWN_MANDATORY_CHUNKS.contains(&&chunk.id))
+    {
+        return Err(NsfError::UnsupportedChunk {
+            offset: *offset,
+            id: chunk.id,
+        });
+    }
+
+    let (info_offset, info_chunk) = find(b"INFO").ok_or(NsfError::MissingChunk {
+        offset: input.len(),
+        id: *b"INFO",
+    })?;
+    let info = parse_chunk_data(parse_info, info_chunk, *info_offset)?;
+
+    let (_, data) = find(b"DATA").ok_or(NsfError::MissingChunk {
+        offset: input.len(),
+        id: *b"DATA",
+    })?;
+
+    let mut bankswitch_init = [0; 8];
+
+    if let Some((_, bank)) = find(b"BANK") {
+        let len = bank.data.len().min(8);
+        bankswitch_init[..len].copy_from_slice(&bank.data[..len]);
+    }
+
+    let (play_speed_ntsc, play_speed_pal) = match find(b"RATE") {
+        Some((offset, rate)) => parse_chunk_data(parse_rate, rate, *offset)?,
+        None => (DEFAULT_PLAY_SPEED_NTSC, None),
+    };
+
+    let flags = find(b"NSF2").and_then(|(_, nsf2)| nsf2.data.first().copied());
+
+    let [song_name, artist_name, copyright_holder, ripper_name] =
+        parse_authors(&chunks)?.unwrap_or_default();
+
+    let metadata: Vec<_> = chunks
+        .iter()
+        .filter(|(_, chunk)| !matches!(&chunk.id, b"INFO" | b"DATA"))
+        .cloned()
+        .collect();
+
+    let header = NsfHeader {
+        version_num: if flags.is_some() { 2 } else { 1 },
+        total_songs: info.total_songs,
+        starting_song: info.starting_song.saturating_add(1),
+        load_address: info.load_address,
+        init_address: info.init_address,
+        play_address: info.play_address,
+        song_name,
+        artist_name,
+        copyright_holder,
+        ripper_name,
+        play_speed_ntsc,
+        bankswitch_init,
+        play_speed_pal: play_speed_pal.unwrap_or(DEFAULT_PLAY_SPEED_PAL),
+        pal_ntsc_bits: info.pal_ntsc_bits,
+        sound_chip_support: info.sound_chip_support,
+        flags: flags.unwrap_or(0),
+        data_length: data.data.len() as u32,
+        track_info: parse_track_info(&metadata)?,
+        metadata: metadata.into_iter().map(|(_, chunk)| chunk).collect(),
+        warnings: vec![],
+    };
+
+    if !lenient {
+        validate_addresses(
+            &header,
+            [*info_offset + 8, *info_offset + 10, *info_offset + 12],
+        )?;
+    }
+
+    Ok((header, data.data.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nsf::{read_nsf, read_nsf_lenient};
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+        chunk.extend_from_slice(id);
+        chunk.extend_from_slice(data);
+
+        chunk
+    }
+
+    /// Two tracks loading, initialising and playing at $8000, starting on the second
+    fn info() -> Vec<u8> {
+        chunk(b"INFO", &[0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0, 0, 2, 1])
+    }
+
+    fn nsfe(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut nsfe = b"NSFE".to_vec();
+
+        for chunk in chunks {
+            nsfe.extend_from_slice(chunk);
+        }
+
+        nsfe
+    }
+
+    #[test]
+    fn reads_nsfe() {
+        let input = nsfe(&[
+            info(),
+            chunk(b"DATA", &[0x60]),
+            chunk(b"tlbl", b"One\0Two\0"),
+            chunk(b"auth", b"Game\0Artist\0"),
+            chunk(b"xtra", b"kept"),
+            chunk(b"NEND", &[]),
+            chunk(b"past", b"the end"),
+        ]);
+
+        let (header, data) = read_nsf(&input).unwrap();
+
+        assert_eq!(data, [0x60]);
+        assert_eq!(header.total_songs, 2);
+        assert_eq!(header.starting_song, 2);
+        assert_eq!(header.play_speed_ntsc, DEFAULT_PLAY_SPEED_NTSC);
+        assert_eq!(header.song_name.decode(), "Game");
+        assert_eq!(header.artist_name.decode(), "Artist");
+        assert_eq!(header.track_label(2), Some("Two"));
+        assert!(header.metadata.iter().any(|chunk| &chunk.id == b"xtra"));
+        assert!(!header.metadata.iter().any(|chunk| &chunk.id == b"past"));
+    }
+
+    #[test]
+    fn truncated_chunk() {
+        let mut data = chunk(b"DATA", &[0x60; 16]);
+        data.truncate(12);
+        let info = info();
+        let data_offset = 4 + info.len();
+
+        let err = read_nsf(&nsfe(&[info, data])).unwrap_err();
+
+        assert!(matches!(err, NsfError::TruncatedChunk { offset } if offset == data_offset));
+    }
+
+    #[test]
+    fn info_too_short() {
+        let input = nsfe(&[chunk(b"INFO", &[0x00, 0x80]), chunk(b"DATA", &[0x60])]);
+
+        let err = read_nsf(&input).unwrap_err();
+
+        assert!(matches!(err, NsfError::TruncatedChunk { offset: 4 }));
+    }
+
+    #[test]
+    fn missing_chunks() {
+        for (input, missing) in [
+            (nsfe(&[chunk(b"DATA", &[0x60])]), b"INFO"),
+            (nsfe(&[info()]), b"DATA"),
+        ] {
+            let err = read_nsf(&input).unwrap_err();
+
+            assert!(matches!(
+                err,
+                NsfError::MissingChunk { offset, id } if offset == input.len() && &id == missing
+            ));
+        }
+    }
+
+    #[test]
+    fn unsupported_mandatory_chunk() {
+        let info = info();
+        let unknown_offset = 4 + info.len();
+        let input = nsfe(&[info, chunk(b"VRC9", &[]), chunk(b"DATA", &[0x60])]);
+
+        let err = read_nsf(&input).unwrap_err();
+
+        assert!(matches!(
+            err,
+            NsfError::UnsupportedChunk { offset, id: [b'V', b'R', b'C', b'9'] }
+                if offset == unknown_offset
+        ));
+    }
+
+    #[test]
+    fn invalid_address() {
+        let data = chunk(b"DATA", &[0x60]);
+        // The play address is at $04 in INFO's data, after its length and ID
+        let play_offset = 4 + data.len() + 8 + 4;
+        let info = chunk(b"INFO", &[0x00, 0x80, 0x00, 0x80, 0x00, 0x40, 0, 0]);
+        let input = nsfe(&[data, info]);
+
+        let err = read_nsf(&input).unwrap_err();
+
+        assert!(matches!(
+            err,
+            NsfError::InvalidAddress {
+                offset,
+                field: "play address",
+                address: 0x4000,
+            } if offset == play_offset
+        ));
+        assert!(read_nsf_lenient(&input).is_ok());
+    }
+}
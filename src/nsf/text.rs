@@ -0,0 +1,263 @@
+use encoding_rs::{Encoding, SHIFT_JIS, UTF_8, WINDOWS_1252};
+use std::{
+    ffi::{CString, NulError},
+    fmt,
+};
+
+/// The encodings NSF text shows up in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextEncoding {
+    Utf8,
+    /// Western rips, predating UTF-8 being the norm
+    Windows1252,
+    /// Japanese rips
+    ShiftJis,
+}
+
+impl TextEncoding {
+    fn encoding(self) -> &'static Encoding {
+        match self {
+            TextEncoding::Utf8 => UTF_8,
+            TextEncoding::Windows1252 => WINDOWS_1252,
+            TextEncoding::ShiftJis => SHIFT_JIS,
+        }
+    }
+}
+
+/// A text field from an NSF header or NSFe chunk, kept as the raw bytes it was read from. The
+/// text is everything up to the first NUL; whatever follows it is ignored, but kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NsfText {
+    raw: Vec<u8>,
+}
+
+impl NsfText {
+    /// Text from a string, which mustn't have a NUL in it
+    pub fn new(text: &str) -> Result<Self, NulError> {
+        Ok(NsfText {
+            raw: CString::new(text)?.into_bytes(),
+        })
+    }
+
+    pub fn from_raw(raw: &[u8]) -> Self {
+        NsfText { raw: raw.to_vec() }
+    }
+
+    /// All of the bytes, including the NUL and anything after it
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// The bytes up to the first NUL
+    pub fn bytes(&self) -> &[u8] {
+        let len = self
+            .raw
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(self.raw.len());
+
+        &self.raw[..len]
+    }
+
+    /// The longest start of `bytes` that's at most `max_len` bytes long and doesn't split a
+    /// character in the text's `encoding`
+    pub fn truncated(&self, max_len: usize) -> &[u8] {
+        let bytes = self.bytes();
+
+        if bytes.len() <= max_len {
+            return bytes;
+        }
+
+        let len = match self.encoding() {
+            TextEncoding::Utf8 => (0..=max_len)
+                .rev()
+                .find(|&len| std::str::from_utf8(&bytes[..len]).is_ok())
+                .unwrap_or(0),
+            TextEncoding::ShiftJis => {
+                let mut len = 0;
+
+                loop {
+                    let width = match bytes[len] {
+                        0x81..=0x9F | 0xE0..=0xFC => 2,
+                        _ => 1,
+                    };
+
+                    if len + width > max_len {
+                        break len;
+                    }
+
+                    len += width;
+                }
+            }
+            TextEncoding::Windows1252 => max_len,
+        };
+
+        &bytes[..len]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes().is_empty()
+    }
+
+    /// The text in `encoding`, with anything that isn't valid in it replaced by U+FFFD
+    pub fn decode_as(&self, encoding: TextEncoding) -> String {
+        let (text, _) = encoding
+            .encoding()
+            .decode_without_bom_handling(self.bytes());
+
+        text.into_owned()
+    }
+
+    /// The encoding the text looks to be in: UTF-8 if it's valid UTF-8, Shift-JIS if it looks
+    /// like Shift-JIS (see `looks_like_shift_jis`), and otherwise Windows-1252, in which every
+    /// byte means something
+    pub fn encoding(&self) -> TextEncoding {
+        let bytes = self.bytes();
+
+        if std::str::from_utf8(bytes).is_ok() {
+            TextEncoding::Utf8
+        } else if looks_like_shift_jis(bytes)
+            && SHIFT_JIS
+                .decode_without_bom_handling_and_without_replacement(bytes)
+                .is_some()
+        {
+            TextEncoding::ShiftJis
+        } else {
+            TextEncoding::Windows1252
+        }
+    }
+
+    /// The text in the encoding it looks to be in. Where that guesses wrong, `decode_as` takes
+    /// the encoding to use.
+    pub fn decode(&self) -> String {
+        self.decode_as(self.encoding())
+    }
+}
+
+/// Whether `bytes` look like Shift-JIS text, rather than Windows-1252 text that happens to be
+/// valid Shift-JIS: they have at least one double-byte character or run of half-width katakana
+/// (0xA1-0xDF), and no lone half-width katakana (where Windows-1252 keeps ©, ¼, Ä, Ñ and the
+/// like, which seldom come two in a row) or characters from the user-defined area (lead bytes
+/// 0xF0-0xFC, where it keeps ð-ü), which no real title uses.
+fn looks_like_shift_jis(bytes: &[u8]) -> bool {
+    let mut japanese = false;
+    let mut rest = bytes;
+
+    while let Some((&byte, after)) = rest.split_first() {
+        rest = match byte {
+            0x00..=0x7F => after,
+            0x81..=0x9F | 0xE0..=0xEF => match after.split_first() {
+                Some((0x40..=0x7E | 0x80..=0xFC, after)) => {
+                    japanese = true;
+                    after
+                }
+                _ => return false,
+            },
+            0xA1..=0xDF => {
+                let run = after
+                    .iter()
+                    .take_while(|byte| (0xA1..=0xDF).contains(*byte))
+                    .count();
+
+                if run == 0 {
+                    return false;
+                }
+
+                japanese = true;
+                &after[run..]
+            }
+            _ => return false,
+        };
+    }
+
+    japanese
+}
+
+impl fmt::Display for NsfText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.decode())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shift_jis(text: &str) -> NsfText {
+        NsfText::from_raw(&SHIFT_JIS.encode(text).0)
+    }
+
+    #[test]
+    fn text_stops_at_the_first_nul() {
+        let text = NsfText::from_raw(b"Title\0junk\0");
+
+        assert_eq!(text.bytes(), b"Title");
+        assert_eq!(text.raw(), b"Title\0junk\0");
+        assert_eq!(text.decode(), "Title");
+        assert!(NsfText::from_raw(b"\0Title").is_empty());
+    }
+
+    #[test]
+    fn utf8() {
+        let text = NsfText::new("Café ♪").unwrap();
+
+        assert_eq!(text.encoding(), TextEncoding::Utf8);
+        assert_eq!(text.decode(), "Café ♪");
+    }
+
+    #[test]
+    fn shift_jis_titles() {
+        for title in ["ドラゴンクエスト", "ｽｰﾊﾟｰﾏﾘｵ", "ロックマン2 ﾜｲﾘｰ"]
+        {
+            let text = shift_jis(title);
+
+            assert_eq!(text.encoding(), TextEncoding::ShiftJis, "{title}");
+            assert_eq!(text.decode(), title);
+        }
+    }
+
+    #[test]
+    fn windows_1252_titles() {
+        for (raw, title) in [
+            (&b"Caf\xE9"[..], "Café"),
+            (b"\xA9 1986 Nintendo", "© 1986 Nintendo"),
+            (b"\xC4pfel & Birnen", "Äpfel & Birnen"),
+        ] {
+            let text = NsfText::from_raw(raw);
+
+            assert_eq!(text.encoding(), TextEncoding::Windows1252, "{title}");
+            assert_eq!(text.decode(), title);
+        }
+    }
+
+    #[test]
+    fn truncation_keeps_characters_whole() {
+        let utf8 = NsfText::new("aé♪b").unwrap();
+
+        for max_len in 0..=utf8.bytes().len() {
+            let truncated = utf8.truncated(max_len);
+
+            assert!(truncated.len() <= max_len);
+            assert!(std::str::from_utf8(truncated).is_ok(), "{max_len}");
+        }
+
+        assert_eq!(utf8.truncated(4), "aé".as_bytes());
+
+        let sjis = shift_jis("ロックﾏﾝ");
+
+        for max_len in 0..=sjis.bytes().len() {
+            let truncated = sjis.truncated(max_len);
+
+            assert!(truncated.len() <= max_len);
+            assert!(
+                SHIFT_JIS
+                    .decode_without_bom_handling_and_without_replacement(truncated)
+                    .is_some(),
+                "{max_len}"
+            );
+        }
+
+        assert_eq!(sjis.truncated(5), &sjis.bytes()[..4]);
+        assert_eq!(sjis.truncated(7), &sjis.bytes()[..7]);
+    }
+}
@@ -0,0 +1,342 @@
+use std::time::Duration;
+
+use super::{Chunk, NsfHeader, NsfText, METADATA_FLAG};
+
+/// Chunks the writers build from the header itself, rather than copying them from `metadata`
+const GENERATED_CHUNKS: [&[u8; 4]; 11] = [
+    b"INFO", b"DATA", b"BANK", b"RATE", b"NSF2", b"auth", b"tlbl", b"time", b"fade", b"plst",
+    b"NEND",
+];
+
+/// The longest text field an NSF header holds, leaving room for the NUL
+pub(super) const MAX_TEXT_LEN: usize = 31;
+
+impl Chunk {
+    fn new(id: &[u8; 4], data: Vec<u8>) -> Self {
+        Chunk { id: *id, data }
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.id);
+        out.extend_from_slice(&self.data);
+    }
+}
+
+/// A header text field, cut down to 31 bytes without splitting a character
+fn text_field(text: &NsfText) -> [u8; 32] {
+    let bytes = text.truncated(MAX_TEXT_LEN);
+
+    let mut field = [0; 32];
+    field[..bytes.len()].copy_from_slice(bytes);
+
+    field
+}
+
+/// NUL-terminated strings, as in tlbl and auth
+fn strings_chunk_data<'a>(strings: impl IntoIterator<Item = &'a [u8]>) -> Vec<u8> {
+    let mut data = vec![];
+
+    for string in strings {
+        data.extend_from_slice(string);
+        data.push(0);
+    }
+
+    data
+}
+
+/// time and fade: a signed 32-bit number of milliseconds per track, -1 if it isn't known
+fn durations_chunk_data(durations: &[Option<Duration>]) -> Vec<u8> {
+    durations
+        .iter()
+        .flat_map(|duration| {
+            duration
+                .map_or(-1, |duration| {
+                    i32::try_from(duration.as_millis()).unwrap_or(i32::MAX)
+                })
+                .to_le_bytes()
+        })
+        .collect()
+}
+
+fn authors(header: &NsfHeader) -> [&NsfText; 4] {
+    [
+        &header.song_name,
+        &header.artist_name,
+        &header.copyright_holder,
+        &header.ripper_name,
+    ]
+}
+
+/// The chunks describing the authors and tracks, plus whatever chunks from `metadata` the
+/// writers don't generate themselves
+fn metadata_chunks(header: &NsfHeader, with_auth: bool) -> Vec<Chunk> {
+    let track_info = &header.track_info;
+    let mut chunks = vec![];
+
+    if with_auth {
+        let authors = authors(header).map(|author| author.bytes());
+        chunks.push(Chunk::new(b"auth", strings_chunk_data(authors)));
+    }
+
+    if let Some(playlist) = &track_info.playlist {
+        chunks.push(Chunk::new(b"plst", playlist.clone()));
+    }
+
+    if !track_info.times.is_empty() {
+        chunks.push(Chunk::new(b"time", durations_chunk_data(&track_info.times)));
+    }
+
+    if !track_info.fades.is_empty() {
+        chunks.push(Chunk::new(b"fade", durations_chunk_data(&track_info.fades)));
+    }
+
+    if !track_info.labels.is_empty() {
+        let labels = track_info.labels.iter().map(|label| label.as_bytes());
+        chunks.push(Chunk::new(b"tlbl", strings_chunk_data(labels)));
+    }
+
+    chunks.extend(
+        header
+            .metadata
+            .iter()
+            .filter(|chunk| !GENERATED_CHUNKS.contains(&&chunk.id))
+            .cloned(),
+    );
+
+    chunks
+}
+
+fn write_header(header: &NsfHeader, version_num: u8, flags: u8, data_length: u32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(0x80);
+
+    out.extend_from_slice(b"NESM\x1a");
+    out.push(version_num);
+    out.push(header.total_songs);
+    out.push(header.starting_song);
+    out.extend_from_slice(&header.load_address.to_le_bytes());
+    out.extend_from_slice(&header.init_address.to_le_bytes());
+    out.extend_from_slice(&header.play_address.to_le_bytes());
+    out.extend_from_slice(&text_field(&header.song_name));
+    out.extend_from_slice(&text_field(&header.artist_name));
+    out.extend_from_slice(&text_field(&header.copyright_holder));
+    out.extend_from_slice(&header.play_speed_ntsc.to_le_bytes());
+    out.extend_from_slice(&header.bankswitch_init);
+    out.extend_from_slice(&header.play_speed_pal.to_le_bytes());
+    out.push(header.pal_ntsc_bits);
+    out.push(header.sound_chip_support);
+    out.push(flags);
+    out.extend_from_slice(&data_length.to_le_bytes()[..3]);
+
+    out
+}
+
+/// An NSF file holding `header` and the program `data`, without any metadata. The header keeps
+/// its version, but text fields are cut down to 31 bytes and the data length is left at 0.
+pub fn write_nsf(header: &NsfHeader, data: &[u8]) -> Vec<u8> {
+    let version_num = header.version_num.clamp(1, 2);
+    let flags = if version_num >= 2 {
+        header.flags & !METADATA_FLAG
+    } else {
+        0
+    };
+
+    let mut out = write_header(header, version_num, flags, 0);
+    out.extend_from_slice(data);
+
+    out
+}
+
+/// An NSF2 file holding `header`, the program `data` and metadata chunks after it: track
+/// names, times, fades and playlist, whatever else is in `metadata`, and an auth chunk if the
+/// ripper is known or a name is too long for the header.
+pub fn write_nsf2(header: &NsfHeader, data: &[u8]) -> Vec<u8> {
+    let with_auth = !header.ripper_name.is_empty()
+        || authors(header)
+            .iter()
+            .any(|author| author.bytes().len() > MAX_TEXT_LEN);
+
+    let chunks = metadata_chunks(header, with_auth);
+
+    let mut out = write_header(header, 2, header.flags | METADATA_FLAG, data.len() as u32);
+    out.extend_from_slice(data);
+
+    for chunk in &chunks {
+        chunk.write(&mut out);
+    }
+
+    Chunk::new(b"NEND", vec![]).write(&mut out);
+
+    out
+}
+
+/// An NSFe file holding `header`, the program `data` and its metadata
+pub fn write_nsfe(header: &NsfHeader, data: &[u8]) -> Vec<u8> {
+    let mut out = b"NSFE".to_vec();
+
+    let mut info = vec![];
+    info.extend_from_slice(&header.load_address.to_le_bytes());
+    info.extend_from_slice(&header.init_address.to_le_bytes());
+    info.extend_from_slice(&header.play_address.to_le_bytes());
+    info.push(header.pal_ntsc_bits);
+    info.push(header.sound_chip_support);
+    info.push(header.total_songs);
+    info.push(header.starting_song.saturating_sub(1));
+
+    Chunk::new(b"INFO", info).write(&mut out);
+
+    if header.is_bankswitched() {
+        Chunk::new(b"BANK", header.bankswitch_init.to_vec()).write(&mut out);
+    }
+
+    let mut rate = header.play_speed_ntsc.to_le_bytes().to_vec();
+    rate.extend_from_slice(&header.play_speed_pal.to_le_bytes());
+    Chunk::new(b"RATE", rate).write(&mut out);
+
+    if header.version_num >= 2 {
+        Chunk::new(b"NSF2", vec![header.flags & !METADATA_FLAG]).write(&mut out);
+    }
+
+    Chunk::new(b"DATA", data.to_vec()).write(&mut out);
+
+    for chunk in metadata_chunks(header, true) {
+        chunk.write(&mut out);
+    }
+
+    Chunk::new(b"NEND", vec![]).write(&mut out);
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expansion::ExpansionChips;
+    use crate::nsf::{read_nsf, TrackInfo, IRQ_FLAG, NON_RETURNING_INIT_FLAG};
+
+    const DATA: [u8; 3] = [0xA9, 0x00, 0x60];
+
+    /// A three-track tune with every field the writers carry set to something other than its
+    /// default
+    fn header() -> NsfHeader {
+        let mut nsf = b"NESM\x1a\x01\x03\x02\x00\x80\x00\x80\x03\x80".to_vec();
+        nsf.resize(0x80, 0);
+        let (mut header, _) = read_nsf(&nsf).unwrap();
+
+        header.song_name = NsfText::new("Song").unwrap();
+        header.artist_name = NsfText::new("Artist").unwrap();
+        header.copyright_holder = NsfText::new("1986 Company").unwrap();
+        header.play_speed_ntsc = 16639;
+        header.play_speed_pal = 19997;
+        header.bankswitch_init = [0, 1, 2, 3, 4, 5, 6, 7];
+        header.pal_ntsc_bits = 0b10;
+        header.sound_chip_support = ExpansionChips::VRC6.bits();
+
+        header
+    }
+
+    fn track_info() -> TrackInfo {
+        TrackInfo {
+            labels: vec!["One".into(), "Two".into(), "Three".into()],
+            times: vec![
+                Some(Duration::from_secs(90)),
+                None,
+                Some(Duration::from_millis(1500)),
+            ],
+            fades: vec![Some(Duration::from_secs(5))],
+            playlist: Some(vec![2, 0, 1]),
+        }
+    }
+
+    fn assert_same_header(read: &NsfHeader, written: &NsfHeader) {
+        assert_eq!(read.total_songs, written.total_songs);
+        assert_eq!(read.starting_song, written.starting_song);
+        assert_eq!(read.load_address, written.load_address);
+        assert_eq!(read.init_address, written.init_address);
+        assert_eq!(read.play_address, written.play_address);
+        assert_eq!(read.song_name.bytes(), written.song_name.bytes());
+        assert_eq!(read.artist_name.bytes(), written.artist_name.bytes());
+        assert_eq!(
+            read.copyright_holder.bytes(),
+            written.copyright_holder.bytes()
+        );
+        assert_eq!(read.play_speed_ntsc, written.play_speed_ntsc);
+        assert_eq!(read.play_speed_pal, written.play_speed_pal);
+        assert_eq!(read.bankswitch_init, written.bankswitch_init);
+        assert_eq!(read.pal_ntsc_bits, written.pal_ntsc_bits);
+        assert_eq!(read.sound_chip_support, written.sound_chip_support);
+    }
+
+    #[test]
+    fn nsf_round_trip() {
+        let mut header = header();
+        header.track_info = track_info();
+
+        let (read, data) = read_nsf(&write_nsf(&header, &DATA)).unwrap();
+
+        assert_same_header(&read, &header);
+        assert_eq!(read.version_num, 1);
+        assert_eq!(data, DATA);
+        // Plain NSFs have nowhere to put metadata
+        assert_eq!(read.track_info, TrackInfo::default());
+    }
+
+    #[test]
+    fn nsf2_round_trip() {
+        let mut header = header();
+        header.flags = IRQ_FLAG;
+        header.ripper_name = NsfText::new("Ripper").unwrap();
+        header.track_info = track_info();
+        header.metadata = vec![Chunk::new(b"xtra", b"kept".to_vec())];
+
+        let (read, data) = read_nsf(&write_nsf2(&header, &DATA)).unwrap();
+
+        assert_same_header(&read, &header);
+        assert_eq!(read.version_num, 2);
+        assert_eq!(read.flags, IRQ_FLAG | METADATA_FLAG);
+        assert_eq!(read.data_length, DATA.len() as u32);
+        assert_eq!(data, DATA);
+        assert_eq!(read.ripper_name.bytes(), b"Ripper");
+        assert_eq!(read.track_info, track_info());
+        assert!(read
+            .metadata
+            .contains(&Chunk::new(b"xtra", b"kept".to_vec())));
+    }
+
+    #[test]
+    fn nsfe_round_trip() {
+        let mut header = header();
+        header.version_num = 2;
+        header.flags = NON_RETURNING_INIT_FLAG;
+        header.ripper_name = NsfText::new("Ripper").unwrap();
+        header.track_info = track_info();
+
+        let (read, data) = read_nsf(&write_nsfe(&header, &DATA)).unwrap();
+
+        assert_same_header(&read, &header);
+        assert_eq!(read.version_num, 2);
+        assert_eq!(read.flags, NON_RETURNING_INIT_FLAG);
+        assert_eq!(data, DATA);
+        assert_eq!(read.ripper_name.bytes(), b"Ripper");
+        assert_eq!(read.track_info, track_info());
+    }
+
+    #[test]
+    fn long_names() {
+        let mut header = header();
+        // 30 bytes, then a character that'd take the name past 31
+        let name = format!("{}é", "a".repeat(30));
+        header.song_name = NsfText::new(&name).unwrap();
+
+        // The header field stops short of the character rather than splitting it...
+        let (read, _) = read_nsf(&write_nsf(&header, &DATA)).unwrap();
+        assert_eq!(read.song_name.decode(), "a".repeat(30));
+
+        // ...but an NSF2's auth chunk and an NSFe have the whole name
+        let (read, _) = read_nsf(&write_nsf2(&header, &DATA)).unwrap();
+        assert_eq!(read.song_name.decode(), name);
+
+        let (read, _) = read_nsf(&write_nsfe(&header, &DATA)).unwrap();
+        assert_eq!(read.song_name.decode(), name);
+    }
+}
@@ -0,0 +1,85 @@
+use tetanes::common::NesRegion;
+
+use crate::nsf::{NsfHeader, NsfRegion};
+
+/// The console timing a piece of music is played back with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl Region {
+    /// The region an NSF asks to be played back in. Dual-region tunes default to NTSC.
+    pub fn for_nsf(header: &NsfHeader) -> Self {
+        match header.region() {
+            NsfRegion::Pal => Region::Pal,
+            NsfRegion::Ntsc | NsfRegion::Dual => Region::Ntsc,
+        }
+    }
+
+    /// The 2A03/2A07 (or UA6527P for Dendy) CPU clock, derived from the master clock
+    pub fn cpu_clock_hz(self) -> f64 {
+        match self {
+            Region::Ntsc => 236_250_000.0 / 11.0 / 12.0,
+            Region::Pal => 26_601_712.5 / 16.0,
+            Region::Dendy => 26_601_712.5 / 15.0,
+        }
+    }
+
+    /// The number of CPU cycles in one video frame
+    pub fn cpu_cycles_per_frame(self) -> f64 {
+        match self {
+            Region::Ntsc => 29_780.5,
+            Region::Pal => 33_247.5,
+            Region::Dendy => 35_464.0,
+        }
+    }
+
+    pub fn frame_rate_hz(self) -> f64 {
+        self.cpu_clock_hz() / self.cpu_cycles_per_frame()
+    }
+}
+
+impl From<Region> for NesRegion {
+    fn from(region: Region) -> Self {
+        match region {
+            Region::Ntsc => NesRegion::Ntsc,
+            Region::Pal => NesRegion::Pal,
+            Region::Dendy => NesRegion::Dendy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether `a` and `b` are within `tolerance` of each other
+    fn close(a: f64, b: f64, tolerance: f64) -> bool {
+        (a - b).abs() <= tolerance
+    }
+
+    #[test]
+    fn cpu_clocks() {
+        assert!(close(Region::Ntsc.cpu_clock_hz(), 1_789_772.727, 0.001));
+        assert!(close(Region::Pal.cpu_clock_hz(), 1_662_607.031, 0.001));
+        assert!(close(Region::Dendy.cpu_clock_hz(), 1_773_447.5, 0.001));
+    }
+
+    #[test]
+    fn cycles_per_frame() {
+        assert_eq!(Region::Ntsc.cpu_cycles_per_frame(), 29_780.5);
+        assert_eq!(Region::Pal.cpu_cycles_per_frame(), 33_247.5);
+        assert_eq!(Region::Dendy.cpu_cycles_per_frame(), 35_464.0);
+    }
+
+    #[test]
+    fn frame_rates() {
+        assert!(close(Region::Ntsc.frame_rate_hz(), 60.0988, 0.0001));
+        assert!(close(Region::Pal.frame_rate_hz(), 50.0070, 0.0001));
+        assert!(close(Region::Dendy.frame_rate_hz(), 50.0070, 0.0001));
+    }
+}
@@ -0,0 +1,348 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::ensure;
+
+use crate::driver::NsfDriver;
+use crate::expansion::ExpansionOptions;
+use crate::filter::{FilterChain, FilterPreset};
+use crate::nsf::NsfHeader;
+use crate::wav::write_samples_wav;
+
+/// Samples per second of a render
+pub const SAMPLE_RATE: u32 = 44_100;
+
+/// How long to render a track for when the NSF doesn't say how long it is
+pub const DEFAULT_TRACK_DURATION: Duration = Duration::from_secs(150);
+
+/// The samples of one track, produced by clocking an `NsfDriver` as fast as it'll go.
+///
+/// Samples are in -1.0..=1.0, after the front-loader's output filters (which take out the
+/// mixer's DC offset). If the emulator fails partway through, the render ends early and
+/// `take_error` says why.
+pub struct RenderTrack {
+    driver: NsfDriver,
+    filter: FilterChain,
+    pending: VecDeque<f64>,
+    samples_remaining: usize,
+    /// How many samples at the end to fade out over
+    fade_samples: usize,
+    error: Option<anyhow::Error>,
+}
+
+impl RenderTrack {
+    /// Fade out linearly over the last `fade` of the render
+    pub fn with_fade(mut self, fade: Duration) -> Self {
+        self.fade_samples = duration_samples(fade).min(self.samples_remaining);
+        self
+    }
+
+    /// Why the render ended early, if it did
+    pub fn take_error(&mut self) -> Option<anyhow::Error> {
+        self.error.take()
+    }
+}
+
+impl Iterator for RenderTrack {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.samples_remaining == 0 {
+            return None;
+        }
+
+        while self.pending.is_empty() {
+            if let Err(err) = self.driver.step() {
+                self.error = Some(err);
+                self.samples_remaining = 0;
+                return None;
+            }
+
+            self.pending.extend(self.driver.take_samples());
+        }
+
+        let sample = self.pending.pop_front()?;
+        let filtered = self.filter.process(sample);
+
+        let gain = if self.samples_remaining < self.fade_samples {
+            self.samples_remaining as f64 / self.fade_samples as f64
+        } else {
+            1.0
+        };
+
+        self.samples_remaining -= 1;
+
+        Some((filtered * gain).clamp(-1.0, 1.0) as f32)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.samples_remaining, Some(self.samples_remaining))
+    }
+}
+
+/// `duration` of `track` (counting from 1) of the NSF, at `SAMPLE_RATE` samples a second, with
+/// its expansion chips set up with `options`.
+/// No window or audio device is involved, so this runs anywhere.
+pub fn render_track(
+    header: &NsfHeader,
+    data: &[u8],
+    track: u8,
+    duration: Duration,
+    options: &ExpansionOptions,
+) -> anyhow::Result<RenderTrack> {
+    let sample_rate = f64::from(SAMPLE_RATE);
+    let driver = NsfDriver::new(header.clone(), data, track, sample_rate, options)?;
+
+    Ok(RenderTrack {
+        driver,
+        filter: FilterChain::new(FilterPreset::NesFrontLoader, sample_rate),
+        pending: VecDeque::new(),
+        samples_remaining: duration_samples(duration),
+        fade_samples: 0,
+        error: None,
+    })
+}
+
+/// How long to render `track` for, and how long to fade out over at the end of that: its time
+/// and fade from the NSF's metadata, if it has them
+pub fn track_duration(header: &NsfHeader, track: u8) -> (Duration, Duration) {
+    let fade = header.track_fade(track).unwrap_or_default();
+
+    header
+        .track_time(track)
+        .map_or((DEFAULT_TRACK_DURATION, Duration::ZERO), |time| {
+            (time + fade, fade)
+        })
+}
+
+fn duration_samples(duration: Duration) -> usize {
+    (duration.as_secs_f64() * f64::from(SAMPLE_RATE)).round() as usize
+}
+
+/// Where `render_album` puts each track, unless told otherwise
+pub const DEFAULT_FILE_NAME_TEMPLATE: &str = "{track} - {title}.wav";
+
+/// The name of `track`: its label from the NSFe/NSF2 tlbl metadata, or "Track N" if it hasn't
+/// got one
+pub fn track_title(header: &NsfHeader, track: u8) -> String {
+    header
+        .track_label(track)
+        .filter(|label| !label.is_empty())
+        .map_or_else(|| format!("Track {track}"), str::to_string)
+}
+
+/// RIFF INFO tags for `track`: its title, the tune's name as the album, the artist, copyright
+/// and track number
+pub fn track_tags(header: &NsfHeader, track: u8) -> Vec<([u8; 4], String)> {
+    let mut tags = vec![(*b"INAM", track_title(header, track))];
+
+    let text_tags = [
+        (*b"IPRD", &header.song_name),
+        (*b"IART", &header.artist_name),
+        (*b"ICOP", &header.copyright_holder),
+    ];
+
+    tags.extend(
+        text_tags
+            .into_iter()
+            .filter(|(_, text)| !text.is_empty())
+            .map(|(id, text)| (id, text.to_string())),
+    );
+
+    tags.push((*b"ITRK", track.to_string()));
+
+    tags
+}
+
+/// A file name for `track` from `template`, in which `{track}` is the track number (padded
+/// with zeroes so they sort) and `{title}` is `track_title`. Anything in the title that can't
+/// go in a file name is replaced with `_`.
+pub fn track_file_name(template: &str, header: &NsfHeader, track: u8) -> String {
+    let width = header.total_songs.to_string().len().max(2);
+
+    let title: String = track_title(header, track)
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    template
+        .replace("{track}", &format!("{track:0width$}"))
+        .replace("{title}", title.trim())
+}
+
+/// Render `track` to a tagged WAV at `path`, for `duration` and fading out over the last `fade`
+/// of it, with its expansion chips set up with `options`
+pub fn write_track_wav(
+    header: &NsfHeader,
+    data: &[u8],
+    track: u8,
+    (duration, fade): (Duration, Duration),
+    options: &ExpansionOptions,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let mut samples = render_track(header, data, track, duration, options)?.with_fade(fade);
+
+    let mut wav_output_file = BufWriter::with_capacity(1 << 20, File::create(path)?);
+    write_samples_wav(
+        &mut samples,
+        &track_tags(header, track),
+        &mut wav_output_file,
+    )?;
+    wav_output_file.flush()?;
+
+    if let Some(err) = samples.take_error() {
+        return Err(err.context(format!("track {track} failed partway through")));
+    }
+
+    Ok(())
+}
+
+/// Render every track of the NSF into `dir`, named after `template` (see `track_file_name`),
+/// each for as long as `track_duration` says. Returns the files written, in the order of the
+/// NSF's playlist if it has one (see `NsfHeader::playlist`), and otherwise in track order. A
+/// track the playlist has more than once is only rendered once.
+///
+/// `template` has to have `{track}` in it, or every track would get the same file name.
+pub fn render_album(
+    header: &NsfHeader,
+    data: &[u8],
+    dir: &Path,
+    template: &str,
+    options: &ExpansionOptions,
+) -> anyhow::Result<Vec<PathBuf>> {
+    ensure!(
+        template.contains("{track}"),
+        "file name template {template:?} has no {{track}}, so the tracks would overwrite each other"
+    );
+
+    fs::create_dir_all(dir)?;
+
+    let mut tracks = header.playlist();
+    let mut seen = HashSet::new();
+    tracks.retain(|&track| seen.insert(track));
+
+    tracks
+        .into_iter()
+        .map(|track| {
+            let path = dir.join(track_file_name(template, header, track));
+            let duration = track_duration(header, track);
+            write_track_wav(header, data, track, duration, options, &path)?;
+
+            Ok(path)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nsf::{read_nsf, TrackInfo};
+
+    /// A tune with `total_songs` tracks, loaded, initialised and played at $8000, where there's
+    /// an RTS
+    fn nsf(total_songs: u8) -> (NsfHeader, Vec<u8>) {
+        let mut nsf = b"NESM\x1a\x01\x01\x01\x00\x80\x00\x80\x00\x80".to_vec();
+        nsf[0x006] = total_songs;
+        nsf.resize(0x80, 0);
+        nsf.push(0x60);
+
+        read_nsf(&nsf).unwrap()
+    }
+
+    #[test]
+    fn render_album_rejects_templates_without_a_track_number() {
+        let (header, data) = nsf(1);
+        let dir = std::env::temp_dir().join("wav-creator-no-track-template");
+
+        let options = ExpansionOptions::default();
+        let err = render_album(&header, &data, &dir, "{title}.wav", &options).unwrap_err();
+
+        assert!(err.to_string().contains("{track}"));
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn file_names() {
+        let (mut header, _) = nsf(12);
+        header.track_info.labels = vec!["".into(), " AC/DC: Live? ".into()];
+
+        assert_eq!(
+            track_file_name(DEFAULT_FILE_NAME_TEMPLATE, &header, 1),
+            "01 - Track 1.wav"
+        );
+        assert_eq!(
+            track_file_name(DEFAULT_FILE_NAME_TEMPLATE, &header, 2),
+            "02 - AC_DC_ Live_.wav"
+        );
+        assert_eq!(
+            track_file_name("{title}/{track}.wav", &header, 12),
+            "Track 12/12.wav"
+        );
+
+        // Padded to as many digits as the last track has
+        header.total_songs = 120;
+
+        assert_eq!(track_file_name("{track}.wav", &header, 3), "003.wav");
+    }
+
+    #[test]
+    fn durations_and_fades() {
+        let (mut header, data) = nsf(3);
+        header.track_info.times =
+            vec![Some(Duration::from_secs(90)), Some(Duration::from_secs(60))];
+        header.track_info.fades = vec![Some(Duration::from_secs(5))];
+
+        assert_eq!(
+            track_duration(&header, 1),
+            (Duration::from_secs(95), Duration::from_secs(5))
+        );
+        assert_eq!(
+            track_duration(&header, 2),
+            (Duration::from_secs(60), Duration::ZERO)
+        );
+        assert_eq!(
+            track_duration(&header, 3),
+            (DEFAULT_TRACK_DURATION, Duration::ZERO)
+        );
+
+        // A fade longer than the render fades it all
+        let options = ExpansionOptions::default();
+        let render = render_track(&header, &data, 1, Duration::from_millis(100), &options)
+            .unwrap()
+            .with_fade(Duration::from_secs(5));
+
+        assert_eq!(render.fade_samples, 4410);
+        assert_eq!(render.count(), 4410);
+    }
+
+    #[test]
+    fn render_album_follows_the_playlist() {
+        let (mut header, data) = nsf(3);
+        header.track_info = TrackInfo {
+            times: vec![Some(Duration::from_millis(10)); 3],
+            playlist: Some(vec![2, 0, 2, 7]),
+            ..TrackInfo::default()
+        };
+        let dir = std::env::temp_dir().join("wav-creator-playlist");
+        // Left over from a run that failed
+        let _ = fs::remove_dir_all(&dir);
+
+        let options = ExpansionOptions::default();
+        let paths = render_album(&header, &data, &dir, "{track}.wav", &options).unwrap();
+        let written = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Each track once, and not the one that doesn't exist
+        assert_eq!(paths, [dir.join("03.wav"), dir.join("01.wav")]);
+        assert_eq!(written, 2);
+    }
+}
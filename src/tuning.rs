@@ -0,0 +1,189 @@
+use crate::region::Region;
+
+/// The number of keys on a piano, A0 through C8
+pub const NUM_PIANO_KEYS: usize = 88;
+/// A4 is the 49th key on a piano
+pub const A4_KEY: usize = 48;
+/// The highest period that fits in a channel's 11-bit timer
+pub const MAX_PERIOD: u16 = 0x07FF;
+
+/// The channels whose pitch is set with an 11-bit timer period
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Pulse,
+    Triangle,
+}
+
+impl Channel {
+    /// How many CPU cycles make up one step of the timer, times the number of steps in the
+    /// channel's waveform: pulse timers are clocked every other cycle through an 8-step
+    /// sequence, the triangle timer every cycle through a 32-step sequence
+    fn cycles_per_period_step(self) -> f64 {
+        match self {
+            Channel::Pulse => 16.0,
+            Channel::Triangle => 32.0,
+        }
+    }
+
+    /// The lowest period that still produces a tone: pulse channels are silenced below 8 and
+    /// the triangle becomes ultrasonic below 2
+    fn min_period(self) -> u16 {
+        match self {
+            Channel::Pulse => 8,
+            Channel::Triangle => 2,
+        }
+    }
+
+    /// The frequency this channel plays at the given timer period
+    pub fn freq(self, region: Region, period: u16) -> f64 {
+        self.raw_freq(region, period.into())
+    }
+
+    fn raw_freq(self, region: Region, period: u32) -> f64 {
+        region.cpu_clock_hz() / (self.cycles_per_period_step() * (f64::from(period) + 1.0))
+    }
+
+    /// The timer period that comes closest to `freq`, whether or not it fits in 11 bits
+    pub fn raw_period(self, region: Region, freq: f64) -> u32 {
+        let period = region.cpu_clock_hz() / (self.cycles_per_period_step() * freq) - 1.0;
+        period.round().max(0.0) as u32
+    }
+}
+
+/// One key of a `Tuning`, quantized to a timer period
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TunedKey {
+    /// 0 is A0, 87 is C8
+    pub key: usize,
+    /// The exact, equal-tempered frequency of the key
+    pub freq: f64,
+    /// The closest 11-bit period, or `None` if the key is out of the channel's range
+    pub period: Option<u16>,
+    /// How far the quantized period is from `freq`, in cents; positive means sharp
+    pub cents_error: f64,
+}
+
+/// Equal-tempered tuning of the piano keys against a reference pitch for A4
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tuning {
+    pub a4_hz: f64,
+    pub region: Region,
+    pub channel: Channel,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Tuning::new(440.0, Region::Ntsc, Channel::Pulse)
+    }
+}
+
+impl Tuning {
+    pub fn new(a4_hz: f64, region: Region, channel: Channel) -> Self {
+        Tuning {
+            a4_hz,
+            region,
+            channel,
+        }
+    }
+
+    /// The frequency of a piano key, where 0 is A0
+    pub fn key_freq(&self, key: usize) -> f64 {
+        self.a4_hz * 2f64.powf((key as f64 - A4_KEY as f64) / 12.0)
+    }
+
+    pub fn key(&self, key: usize) -> TunedKey {
+        let freq = self.key_freq(key);
+        let raw_period = self.channel.raw_period(self.region, freq);
+
+        let period = u16::try_from(raw_period)
+            .ok()
+            .filter(|period| (self.channel.min_period()..=MAX_PERIOD).contains(period));
+
+        let quantized_freq = self.channel.raw_freq(self.region, raw_period);
+
+        TunedKey {
+            key,
+            freq,
+            period,
+            cents_error: cents(quantized_freq, freq),
+        }
+    }
+
+    /// The 11-bit period for a piano key, if the channel can play it
+    pub fn period(&self, key: usize) -> Option<u16> {
+        self.key(key).period
+    }
+
+    /// All 88 piano keys, A0 through C8
+    pub fn piano_keys(&self) -> Vec<TunedKey> {
+        (0..NUM_PIANO_KEYS).map(|key| self.key(key)).collect()
+    }
+}
+
+/// The distance from `reference` to `freq`, in cents
+pub fn cents(freq: f64, reference: f64) -> f64 {
+    1200.0 * (freq / reference).log2()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REGIONS: [Region; 3] = [Region::Ntsc, Region::Pal, Region::Dendy];
+
+    #[test]
+    fn a4_periods() {
+        let period = |region, channel| Tuning::new(440.0, region, channel).period(A4_KEY);
+
+        assert_eq!(period(Region::Ntsc, Channel::Pulse), Some(253));
+        assert_eq!(period(Region::Ntsc, Channel::Triangle), Some(126));
+        assert_eq!(period(Region::Pal, Channel::Pulse), Some(235));
+        assert_eq!(period(Region::Pal, Channel::Triangle), Some(117));
+    }
+
+    #[test]
+    fn keys_out_of_range() {
+        let pulse = Tuning::new(440.0, Region::Ntsc, Channel::Pulse);
+
+        // A1 is the lowest note an 11-bit pulse period reaches
+        assert_eq!(pulse.period(12), Some(2033));
+        assert_eq!(pulse.period(11), None);
+
+        // The triangle's an octave lower, so it goes all the way down to A0
+        let triangle = Tuning::new(440.0, Region::Ntsc, Channel::Triangle);
+
+        assert_eq!(triangle.period(0), Some(2033));
+
+        // Far enough past C8, the periods get too short to make a tone
+        assert!(pulse.period(NUM_PIANO_KEYS - 1).is_some());
+        assert_eq!(pulse.period(130), None);
+        assert_eq!(triangle.period(130), None);
+    }
+
+    #[test]
+    fn cents_error_is_within_half_a_period() {
+        for region in REGIONS {
+            for channel in [Channel::Pulse, Channel::Triangle] {
+                let tuning = Tuning::new(440.0, region, channel);
+
+                for key in tuning.piano_keys() {
+                    let Some(period) = key.period else {
+                        continue;
+                    };
+
+                    // Rounding the period is off by at most half of one, which is furthest in
+                    // cents on the flat side
+                    let period = f64::from(period);
+                    let bound = cents(period + 1.0, period + 0.5);
+
+                    assert!(
+                        key.cents_error.abs() <= bound,
+                        "{region:?} {channel:?} key {}: {} cents",
+                        key.key,
+                        key.cents_error
+                    );
+                }
+            }
+        }
+    }
+}
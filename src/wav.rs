@@ -1,15 +1,21 @@
 use std::fs::File;
-use std::io::{self, BufWriter, Write};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
 use std::path::Path;
 
 use dasp::signal::{self as signal, Signal};
 use dasp::signal::{ConstHz, ScaleAmp, Sine, Square};
 
+use crate::filter::{FilterPreset, Filtered};
+use crate::region::Region;
+use crate::tuning::{Channel, Tuning};
+
 /// Chunk labels
 const RIFF_LABEL: &[u8] = b"RIFF";
 const FORMAT_LABEL: &[u8] = b"WAVE";
 const DATA_LABEL: &[u8] = b"data";
 const FMT_LABEL: &[u8] = b"fmt ";
+const LIST_LABEL: &[u8] = b"LIST";
+const INFO_LABEL: &[u8] = b"INFO";
 
 /// metadata chunk is always 16 bytes
 const FMT_CHUNK_SIZE: u32 = 16;
@@ -24,114 +30,10 @@ const BITS_PER_SAMPLE: u16 = 16;
 /// Mono (for now)
 const NUM_CHANNELS: u16 = 1;
 const NUM_INTERVALS: u32 = 12;
-
-/// The frequencies of the 88 keys on a piano. Note that
-/// octaves of 8 (starting at the bottom, A0, and every 12
-/// semitones after) are exact since they are 2-1 ratios of
-/// A4, which is defined at 440 Hz
-#[allow(clippy::unreadable_literal)]
-const _PIANO_KEY_FREQS: [f64; 88] = [
-    27.5, // A0
-    29.135235094880603,
-    30.86770632850774,
-    32.703195662574814,
-    34.647828872108995,
-    36.70809598967593,
-    38.8908729652601,
-    41.20344461410873,
-    43.65352892912548,
-    46.24930283895429,
-    48.99942949771866,
-    51.91308719749314,
-    55.0, // A1
-    58.270470189761205,
-    61.73541265701548,
-    65.40639132514963,
-    69.29565774421799,
-    73.41619197935186,
-    77.7817459305202,
-    82.40688922821747,
-    87.30705785825096,
-    92.49860567790859,
-    97.99885899543732,
-    103.82617439498628,
-    110.0, // A2
-    116.54094037952241,
-    123.47082531403096,
-    130.81278265029925,
-    138.59131548843598,
-    146.83238395870373,
-    155.5634918610404,
-    164.81377845643493,
-    174.6141157165019,
-    184.99721135581717,
-    195.99771799087463,
-    207.65234878997256,
-    220.0, // A3
-    233.08188075904482,
-    246.94165062806192,
-    261.6255653005985, // middle C
-    277.18263097687196,
-    293.66476791740746,
-    311.1269837220808,
-    329.62755691286986,
-    349.2282314330038,
-    369.99442271163434,
-    391.99543598174927,
-    415.3046975799451,
-    440.0, // A4/A440
-    466.1637615180899,
-    493.8833012561241,
-    523.2511306011974,
-    554.3652619537443,
-    587.3295358348153,
-    622.253967444162,
-    659.2551138257401,
-    698.456462866008,
-    739.988845423269,
-    783.9908719634989,
-    830.6093951598906,
-    880.0, // A5
-    932.3275230361799,
-    987.7666025122483,
-    1046.5022612023947,
-    1108.7305239074885,
-    1174.6590716696305,
-    1244.507934888324,
-    1318.5102276514801,
-    1396.912925732016,
-    1479.977690846538,
-    1567.9817439269978,
-    1661.2187903197812,
-    1760.0, // A6
-    1864.6550460723597,
-    1975.5332050244965,
-    2093.0045224047894,
-    2217.461047814977,
-    2349.318143339261,
-    2489.015869776648,
-    2637.0204553029603,
-    2793.825851464032,
-    2959.955381693076,
-    3135.9634878539955,
-    3322.4375806395624,
-    3520.0, // A7
-    3729.3100921447194,
-    3951.066410048993,
-    4186.009044809579,
-];
-
-// The 11-bit values used to generate piano key notes on an NES
-const PIANO_KEYS_PERIODS: [u16; 88] = [
-    0x0fe3, 0x0efe, 0x0e27, 0x0d5b, 0x0c9c, 0x0be6, 0x0b3b, 0x0a9a, 0x0a01, 0x0972, 0x08ea, 0x086a,
-    0x07f1, 0x077f, 0x0713, 0x06ad, 0x064d, 0x05f3, 0x059d, 0x054c, 0x0500, 0x04b8, 0x0474, 0x0434,
-    0x03f8, 0x03bf, 0x0389, 0x0356, 0x0326, 0x02f9, 0x02ce, 0x02a6, 0x0280, 0x025c, 0x023a, 0x021a,
-    0x01fb, 0x01df, 0x01c4, 0x01ab, 0x0193, 0x017c, 0x0167, 0x0152, 0x013f, 0x012d, 0x011c, 0x010c,
-    0x00fd, 0x00ef, 0x00e1, 0x00d5, 0x00c9, 0x00bd, 0x00b3, 0x00a9, 0x009f, 0x0096, 0x008e, 0x0086,
-    0x007e, 0x0077, 0x0070, 0x006a, 0x0064, 0x005e, 0x0059, 0x0054, 0x004f, 0x004b, 0x0046, 0x0042,
-    0x003f, 0x003b, 0x0038, 0x0034, 0x0031, 0x002f, 0x002c, 0x0029, 0x0027, 0x0025, 0x0023, 0x0021,
-    0x001f, 0x001d, 0x001b, 0x001a,
-];
+/// How much to scale an interval by. Its two notes at full volume add up to twice the 16-bit
+/// range, and high-passing a square wave overshoots at every edge; halving it peaks at around
+/// 80% of the range.
+const INTERVAL_GAIN: f64 = 0.5;
 
 /// WAV file format:
 ///
@@ -182,15 +84,35 @@ fn write_wav_header<T: Write>(
     Ok(())
 }
 
+/// The RIFF chunk size and data chunk size for a WAV holding `num_samples` samples, followed by
+/// `extra_len` bytes of other chunks. Both sizes are 32 bits, so a WAV can't be much past 4 GiB;
+/// anything bigger is an `InvalidInput` error.
+fn riff_sizes(num_samples: u64, bytes_per_frame: u16, extra_len: usize) -> io::Result<(u32, u32)> {
+    let too_big = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{num_samples} samples won't fit in a WAV, which can't be past 4 GiB"),
+        )
+    };
+
+    let data_chunk_size = num_samples
+        .checked_mul(u64::from(bytes_per_frame))
+        .and_then(|size| u32::try_from(size).ok())
+        .ok_or_else(too_big)?;
+    let file_size = (4 + HEADER_SIZE + FMT_CHUNK_SIZE + HEADER_SIZE)
+        .checked_add(data_chunk_size)
+        .and_then(|size| size.checked_add(u32::try_from(extra_len).ok()?))
+        .ok_or_else(too_big)?;
+
+    Ok((file_size, data_chunk_size))
+}
+
 fn _create_sine_wave(sample_rate: f64, hz: f64, amp: f64) -> ScaleAmp<Sine<ConstHz>> {
     signal::rate(sample_rate).const_hz(hz).sine().scale_amp(amp)
 }
 
-#[allow(dead_code)]
-const NTSC_CLOCK_HZ: u32 = 1_789_773;
-
 // TODO support duty cycle?
-fn create_nes_square_wave(sound_bytes: [u8; 4]) -> ScaleAmp<Square<ConstHz>> {
+fn create_nes_square_wave(sound_bytes: [u8; 4], region: Region) -> ScaleAmp<Square<ConstHz>> {
     // Byte 0: DDLC VVVV    Duty (D), envelope loop / length counter halt (L), constant volume (C), volume/envelope (V)
     // Byte 1: EPPP NSSS    Sweep unit: enabled (E), period (P), negate (N), shift (S)
     // Byte 2: TTTT TTTT    Timer low (T)
@@ -207,11 +129,11 @@ fn create_nes_square_wave(sound_bytes: [u8; 4]) -> ScaleAmp<Square<ConstHz>> {
     let period = period_high_bits | period_low_bits;
     let _length_counter_load = sound_bytes[3] >> 3 & 0b1_1111;
 
-    let freq = NTSC_CLOCK_HZ / (16 * (period + 1)) as u32;
+    let freq = Channel::Pulse.freq(region, period);
 
     let amp = i16::MAX as f64 * (volume as f64 / 15.0);
 
-    create_square_wave(SAMPLE_RATE.into(), freq.into(), amp)
+    create_square_wave(SAMPLE_RATE.into(), freq, amp)
 }
 
 #[allow(dead_code)]
@@ -222,46 +144,201 @@ fn create_square_wave(sample_rate: f64, hz: f64, amp: f64) -> ScaleAmp<Square<Co
         .scale_amp(amp)
 }
 
-fn write_wav<T: Write>(duration_s: u32, key_num: usize, wav_output_file: &mut T) -> io::Result<()> {
+/// The two-note intervals from `key_num` up to an octave above it, `duration_s` seconds each.
+/// Fails before writing anything if a key is outside the pulse channel's range.
+fn write_wav<T: Write>(
+    duration_s: u32,
+    key_num: usize,
+    region: Region,
+    wav_output_file: &mut T,
+) -> io::Result<()> {
+    let tuning = Tuning::new(440.0, region, Channel::Pulse);
+    let key_period = |key| {
+        tuning.period(key).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("key {key} is outside the pulse channel's range"),
+            )
+        })
+    };
+    let square_wave = |period: u16| {
+        create_nes_square_wave(
+            [
+                0b1011_0111,
+                0b0,
+                (period & 0xFF) as u8,
+                (period >> 8 & 0b111) as u8,
+            ],
+            region,
+        )
+    };
+
+    let base_period = key_period(key_num)?;
+    let interval_periods = (1..=NUM_INTERVALS as usize)
+        .map(|num_half_steps| key_period(key_num + num_half_steps))
+        .collect::<io::Result<Vec<_>>>()?;
+
     let bytes_per_frame: u16 = (NUM_CHANNELS * BITS_PER_SAMPLE) / 8;
-    let num_samples: u32 = SAMPLE_RATE * duration_s;
-    let data_chunk_size: u32 = num_samples * (bytes_per_frame as u32) * NUM_INTERVALS;
-    let file_size: u32 = 4 + HEADER_SIZE + FMT_CHUNK_SIZE + HEADER_SIZE + data_chunk_size;
+    let num_samples = u64::from(SAMPLE_RATE) * u64::from(duration_s);
+    let (file_size, data_chunk_size) =
+        riff_sizes(num_samples * u64::from(NUM_INTERVALS), bytes_per_frame, 0)?;
 
     write_wav_header(wav_output_file, file_size, bytes_per_frame, data_chunk_size)?;
 
-    for num_half_steps in 1..=NUM_INTERVALS as usize {
-        let base_freq = create_nes_square_wave([
-            0b1011_0111,
-            0b0,
-            (PIANO_KEYS_PERIODS[key_num] & 0xFF) as u8,
-            (PIANO_KEYS_PERIODS[key_num] >> 8 & 0x111) as u8,
-        ]);
-
-        let piano_half_steps_above = create_nes_square_wave([
-            0b1011_0111,
-            0b0,
-            (PIANO_KEYS_PERIODS[key_num + num_half_steps] & 0xFF) as u8,
-            (PIANO_KEYS_PERIODS[key_num + num_half_steps] >> 8 & 0x111) as u8,
-        ]);
-
-        let signal_iter = base_freq
-            .add_amp(piano_half_steps_above)
-            .take(num_samples as usize);
+    for period in interval_periods {
+        let signal_iter = Filtered::new(
+            square_wave(base_period).add_amp(square_wave(period)),
+            FilterPreset::NesFrontLoader,
+            SAMPLE_RATE.into(),
+        )
+        .take(num_samples as usize);
 
         for signal in signal_iter {
-            wav_output_file.write_all(&(signal as i16).to_le_bytes())?;
+            let sample = (signal * INTERVAL_GAIN).clamp(i16::MIN.into(), i16::MAX.into()) as i16;
+            wav_output_file.write_all(&sample.to_le_bytes())?;
         }
     }
 
     Ok(())
 }
 
+/// A LIST chunk of INFO subchunks, e.g. `(*b"INAM", title)`, each a NUL-terminated string
+/// padded to an even length
+fn info_chunk(tags: &[([u8; 4], String)]) -> Vec<u8> {
+    if tags.is_empty() {
+        return vec![];
+    }
+
+    let mut info = INFO_LABEL.to_vec();
+
+    for (id, text) in tags {
+        let mut data = text.as_bytes().to_vec();
+        data.push(0);
+
+        info.extend_from_slice(id);
+        info.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        info.extend_from_slice(&data);
+
+        if data.len() % 2 == 1 {
+            info.push(0);
+        }
+    }
+
+    let mut chunk = LIST_LABEL.to_vec();
+    chunk.extend_from_slice(&(info.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(&info);
+
+    chunk
+}
+
+/// Write `samples` (in -1.0..=1.0, at 44.1 KHz) as a 16-bit mono WAV, tagged with `tags` (see
+/// `info_chunk`). The sizes in the header aren't known until every sample is written, so
+/// they're filled in afterwards. Fails with `InvalidInput` once the file would be too big for
+/// them (see `riff_sizes`).
+pub fn write_samples_wav<T: Write + Seek>(
+    samples: impl IntoIterator<Item = f32>,
+    tags: &[([u8; 4], String)],
+    wav_output_file: &mut T,
+) -> io::Result<()> {
+    let bytes_per_frame: u16 = (NUM_CHANNELS * BITS_PER_SAMPLE) / 8;
+
+    write_wav_header(wav_output_file, 0, bytes_per_frame, 0)?;
+
+    let mut num_samples: u64 = 0;
+
+    for sample in samples {
+        riff_sizes(num_samples + 1, bytes_per_frame, 0)?;
+
+        let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        wav_output_file.write_all(&sample.to_le_bytes())?;
+        num_samples += 1;
+    }
+
+    let info_chunk = info_chunk(tags);
+    wav_output_file.write_all(&info_chunk)?;
+
+    let (file_size, data_chunk_size) = riff_sizes(num_samples, bytes_per_frame, info_chunk.len())?;
+
+    wav_output_file.seek(SeekFrom::Start(0))?;
+    write_wav_header(wav_output_file, file_size, bytes_per_frame, data_chunk_size)?;
+    wav_output_file.seek(SeekFrom::End(0))?;
+
+    Ok(())
+}
+
 pub fn test_wav() -> anyhow::Result<()> {
     // key 48 is A4, aka A440
     let path = Path::new("a440_intervals.wav");
     let mut wav_output_file = BufWriter::with_capacity(1 << 20, File::create(path)?);
-    write_wav(1, 48, &mut wav_output_file)?;
+    write_wav(1, 48, Region::Ntsc, &mut wav_output_file)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_wav_rejects_keys_the_pulse_channel_cant_play() {
+        let mut out = vec![];
+        let err = write_wav(1, 120, Region::Ntsc, &mut out).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn write_wav_leaves_headroom() {
+        for key in [24, 36, 48, 60, 72] {
+            let mut out = vec![];
+            write_wav(1, key, Region::Ntsc, &mut out).unwrap();
+
+            let peak = out[44..]
+                .chunks_exact(2)
+                .map(|sample| i16::from_le_bytes([sample[0], sample[1]]).unsigned_abs())
+                .max()
+                .unwrap();
+
+            // Loud, but not clipped
+            assert!(
+                (16384..i16::MAX as u16).contains(&peak),
+                "key {key}: peak {peak}"
+            );
+        }
+    }
+
+    #[test]
+    fn write_samples_wav_fills_in_the_header_sizes() {
+        let mut out = io::Cursor::new(vec![]);
+        let tags = [(*b"INAM", "Song".to_string())];
+
+        write_samples_wav([0.0, 1.0, -1.0], &tags, &mut out).unwrap();
+
+        let wav = out.into_inner();
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(wav[offset..offset + 4].try_into().unwrap());
+
+        assert_eq!(&wav[..4], RIFF_LABEL);
+        assert_eq!(u32_at(4) as usize, wav.len() - 8);
+        assert_eq!(&wav[36..40], DATA_LABEL);
+        assert_eq!(u32_at(40), 6);
+        assert_eq!(wav[44..50], [0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80]);
+        assert_eq!(&wav[50..54], LIST_LABEL);
+        assert_eq!(wav[50..], info_chunk(&tags));
+    }
+
+    #[test]
+    fn riff_sizes_stop_at_4_gib() {
+        assert_eq!(riff_sizes(3, 2, 10).unwrap(), (36 + 6 + 10, 6));
+
+        let largest = u64::from(u32::MAX - 36) / 2;
+        assert!(riff_sizes(largest, 2, 0).is_ok());
+
+        let err = riff_sizes(largest + 1, 2, 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let err = riff_sizes(largest, 2, 2).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}